@@ -0,0 +1,405 @@
+use crate::functions::tree::{scan, DirEntry, ScanOptions};
+use crate::utils::cli::{bites2str, parse_size, Styler};
+use crate::utils::parallel::default_jobs;
+use anyhow::Result;
+use clap::Args;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+const BAR_WIDTH: usize = 20;
+
+#[derive(Args, Debug)]
+pub struct DuArgs {
+    #[arg(default_value = ".")]
+    pub path: std::path::PathBuf,
+    /// Number of entries to list
+    #[arg(short = 'n', long = "top", default_value_t = 10)]
+    pub top: usize,
+    /// Collapse entries smaller than this size into a single line, e.g. 100MB
+    #[arg(short = 't', long = "threshold", value_parser = parse_size)]
+    pub threshold: Option<u64>,
+    /// Number of threads reading folders
+    #[arg(short = 'j', long = "threads", default_value_t = default_jobs())]
+    pub threads: usize,
+}
+
+/// File or folder listed by [`disk_usage`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Size in bytes, for folders of all their content
+    pub size: u64,
+}
+
+/// Entries left out of the listing, collapsed into one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtherUsage {
+    /// Number of files and folders neither listed nor in a listed folder
+    pub num_entries: usize,
+    /// Size of these files and folders
+    pub size: u64,
+}
+
+/// Largest entries of a tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskUsage {
+    /// Size of the whole tree, only of the content read if folders could not be read
+    pub total: u64,
+    /// Folders that could not be read completely, `total` is a lower bound then
+    pub num_unreadable: usize,
+    /// Largest files and folders, biggest first
+    pub entries: Vec<UsageEntry>,
+    /// Entries below the threshold or beyond `top`, if a threshold was given
+    pub other: Option<OtherUsage>,
+}
+
+/// #### Size of a folder, of the content read if it could not be read completely.
+///
+/// ##### Arguments
+/// * `num_unreadable`: Folders of unknown size without such subfolders are added here
+fn known_size(dir: &DirEntry, num_unreadable: &mut usize) -> u64 {
+    if let Some(size) = dir.size {
+        return size;
+    }
+    if dir.children_dir.iter().all(|child| child.size.is_some()) {
+        *num_unreadable += 1;
+    }
+    let size_files: u64 = dir.children_file.iter().filter_map(|f| f.size).sum();
+    let size_dirs: u64 = dir
+        .children_dir
+        .iter()
+        .map(|child| known_size(child, num_unreadable))
+        .sum();
+    size_files + size_dirs
+}
+
+fn collect_entries(dir: &DirEntry, entries: &mut Vec<UsageEntry>) {
+    for file in &dir.children_file {
+        entries.push(UsageEntry {
+            path: file.path.clone(),
+            is_dir: false,
+            size: file.size.unwrap_or_default(),
+        });
+    }
+    for child in &dir.children_dir {
+        // folders of unknown size would only be a guess
+        if let Some(size) = child.size {
+            entries.push(UsageEntry {
+                path: child.path.clone(),
+                is_dir: true,
+                size,
+            });
+        }
+        collect_entries(child, entries);
+    }
+}
+
+/// #### Add up the files and folders of a tree left out of the listing.
+///
+/// Listed folders are skipped with all their content, left out folders are added with
+/// their size and only counted for their content.
+///
+/// ##### Arguments
+/// * `listed`: Paths of the listed entries
+/// * `in_left_out`: Whether a parent folder was left out, so its size covers `dir`
+/// * `other`: Count and size of the entries left out are added here
+fn collect_other(
+    dir: &DirEntry,
+    listed: &HashSet<&Path>,
+    in_left_out: bool,
+    other: &mut OtherUsage,
+) {
+    for file in &dir.children_file {
+        if !listed.contains(file.path.as_path()) {
+            other.num_entries += 1;
+            if !in_left_out {
+                other.size += file.size.unwrap_or_default();
+            }
+        }
+    }
+    for child in &dir.children_dir {
+        if listed.contains(child.path.as_path()) {
+            continue;
+        }
+        match child.size {
+            Some(size) => {
+                other.num_entries += 1;
+                if !in_left_out {
+                    other.size += size;
+                }
+                collect_other(child, listed, true, other);
+            }
+            // folders of unknown size are no entries, but their content is
+            None => collect_other(child, listed, in_left_out, other),
+        }
+    }
+}
+
+/// #### Find the largest files and folders anywhere in a tree.
+///
+/// Folders contain the files listed with them, so shares of listed entries can add up
+/// to more than the total.
+///
+/// ##### Arguments
+/// * `root`: Tree scanned with sizes
+/// * `top`: Maximum number of entries listed
+/// * `threshold`: Entries smaller than this are not listed, everything left out of the
+///   listing is collapsed into [`OtherUsage`] then
+/// * `return`: Listed entries and totals
+pub fn disk_usage(root: &DirEntry, top: usize, threshold: Option<u64>) -> DiskUsage {
+    let mut entries = Vec::new();
+    collect_entries(root, &mut entries);
+    let mut num_unreadable: usize = 0;
+    let total = known_size(root, &mut num_unreadable);
+
+    entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    let num_listed = match threshold {
+        Some(threshold) => entries.iter().filter(|e| e.size >= threshold).count(),
+        None => entries.len(),
+    };
+    entries.truncate(num_listed.min(top));
+
+    let other = threshold.map(|_| {
+        let listed: HashSet<&Path> = entries.iter().map(|e| e.path.as_path()).collect();
+        let mut other = OtherUsage {
+            num_entries: 0,
+            size: 0,
+        };
+        collect_other(root, &listed, false, &mut other);
+        other
+    });
+    DiskUsage {
+        total,
+        num_unreadable,
+        entries,
+        other,
+    }
+}
+
+/// Line of the listing with size, share of the total and bar.
+fn format_usage(size: u64, total: u64, name: &str, styler_size: &Styler) -> String {
+    let share = if total > 0 {
+        size as f64 / total as f64
+    } else {
+        0.
+    };
+    let bar = "█".repeat((share * BAR_WIDTH as f64).round() as usize);
+    format!(
+        "{} {:>6.1}% {:<width$} {}",
+        bites2str(size, styler_size),
+        share * 100.,
+        bar,
+        name,
+        width = BAR_WIDTH
+    )
+}
+
+pub fn du(path: &Path, top: usize, threshold: Option<u64>, threads: usize) -> Result<()> {
+    let options = ScanOptions {
+        read_size: true,
+        threads,
+        ..Default::default()
+    };
+    let root_dir = scan(path, &options)?;
+    let usage = disk_usage(&root_dir, top, threshold);
+
+    let styler_size = Styler::build("cyan", "", false, false, "")?;
+    let styler_folder = Styler::build("yellow", "", false, false, "")?;
+    let styler_grayed = Styler::build("gray", "", false, false, "")?;
+
+    if usage.num_unreadable > 0 {
+        println!(
+            "≥ {} in {} {}",
+            bites2str(usage.total, &styler_size).trim(),
+            styler_folder.style(&root_dir.name()),
+            styler_grayed.style(&format!(
+                "({} folders could not be read)",
+                usage.num_unreadable
+            ))
+        );
+    } else {
+        println!(
+            "{} in {}",
+            bites2str(usage.total, &styler_size).trim(),
+            styler_folder.style(&root_dir.name())
+        );
+    }
+    for entry in &usage.entries {
+        let name = entry
+            .path
+            .strip_prefix(&root_dir.path)
+            .unwrap_or(&entry.path)
+            .to_string_lossy();
+        let name = if entry.is_dir {
+            styler_folder.style(&format!("{name}/"))
+        } else {
+            name.to_string()
+        };
+        println!(
+            "{}",
+            format_usage(entry.size, usage.total, &name, &styler_size)
+        );
+    }
+    if let Some(other) = &usage.other {
+        let name = styler_grayed.style(&format!("other ({} entries)", other.num_entries));
+        println!(
+            "{}",
+            format_usage(other.size, usage.total, &name, &styler_size)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_du {
+    use std::fs::{self, create_dir};
+    use tempfile::tempdir;
+
+    use super::{disk_usage, OtherUsage};
+    use crate::functions::tree::{scan, ScanOptions};
+
+    #[test]
+    fn largest_entries() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = tempdir.path().join("root_dir");
+        create_dir(&rootdir).unwrap();
+        fs::write(rootdir.join("small.txt"), "1").unwrap();
+        fs::write(rootdir.join("medium.txt"), "12345").unwrap();
+        let subdir = rootdir.join("subdir");
+        create_dir(&subdir).unwrap();
+        fs::write(subdir.join("large.bin"), "1234567890").unwrap();
+        fs::write(subdir.join("tiny.bin"), "12").unwrap();
+
+        let options = ScanOptions {
+            read_size: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let root_dir_entry = scan(&rootdir, &options).unwrap();
+
+        let usage = disk_usage(&root_dir_entry, 2, None);
+        assert_eq!(usage.total, 18);
+        let listed: Vec<(String, u64)> = usage
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.path.file_name().unwrap().to_string_lossy().to_string(),
+                    e.size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (String::from("subdir"), 12),
+                (String::from("large.bin"), 10)
+            ]
+        );
+        assert!(usage.other.is_none());
+
+        let usage = disk_usage(&root_dir_entry, 10, Some(5));
+        assert_eq!(usage.entries.len(), 3);
+        // subdir covers large.bin and tiny.bin, so medium.txt and subdir leave the small file
+        assert_eq!(
+            usage.other,
+            Some(OtherUsage {
+                num_entries: 1,
+                size: 1
+            })
+        );
+
+        // entries above the threshold cut by top are left out as well
+        let usage = disk_usage(&root_dir_entry, 1, Some(2));
+        assert_eq!(usage.entries.len(), 1);
+        assert_eq!(
+            usage.other,
+            Some(OtherUsage {
+                num_entries: 2,
+                size: 6
+            })
+        );
+        let usage = disk_usage(&root_dir_entry, 1, Some(20));
+        assert!(usage.entries.is_empty());
+        assert_eq!(
+            usage.other,
+            Some(OtherUsage {
+                num_entries: 5,
+                size: 18
+            })
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn unreadable_folder() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.txt"), "12345").unwrap();
+        let subdir = tempdir.path().join("subdir");
+        create_dir(&subdir).unwrap();
+        fs::write(subdir.join("b.txt"), "123").unwrap();
+        create_dir(subdir.join("locked")).unwrap();
+
+        let options = ScanOptions {
+            read_size: true,
+            ..Default::default()
+        };
+        let mut root_dir_entry = scan(tempdir.path(), &options).unwrap();
+        // reading fails as a user only, so the tree is changed as a failed read leaves it
+        root_dir_entry.size = None;
+        let subdir_entry = &mut root_dir_entry.children_dir[0];
+        subdir_entry.size = None;
+        subdir_entry.children_dir[0].have_access = false;
+        subdir_entry.children_dir[0].size = None;
+
+        let usage = disk_usage(&root_dir_entry, 10, Some(1));
+        assert_eq!(usage.total, 8);
+        assert_eq!(usage.num_unreadable, 1);
+        assert_eq!(usage.entries.len(), 2);
+        assert_eq!(
+            usage.other,
+            Some(OtherUsage {
+                num_entries: 0,
+                size: 0
+            })
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn many_entries() {
+        // 50 folders of 200 files with one byte each
+        let tempdir = tempdir().unwrap();
+        for num_dir in 0..50 {
+            let subdir = tempdir.path().join(format!("dir_{num_dir:02}"));
+            create_dir(&subdir).unwrap();
+            for num_file in 0..200 {
+                fs::write(subdir.join(format!("file_{num_file:03}")), "1").unwrap();
+            }
+        }
+        let options = ScanOptions {
+            read_size: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let root_dir_entry = scan(tempdir.path(), &options).unwrap();
+
+        let usage = disk_usage(&root_dir_entry, 5, Some(100));
+        assert_eq!(usage.total, 10000);
+        assert_eq!(usage.entries.len(), 5);
+        assert!(usage.entries[0].path.ends_with("dir_00"));
+        // the other folders are counted with their files, which are in their size already
+        assert_eq!(
+            usage.other,
+            Some(OtherUsage {
+                num_entries: 45 * 201,
+                size: 45 * 200
+            })
+        );
+
+        tempdir.close().unwrap();
+    }
+}
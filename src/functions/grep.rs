@@ -0,0 +1,331 @@
+use crate::functions::sed::detect_encoding;
+use crate::utils::cli::{print_line, Styler, INDENT};
+use crate::utils::encoding::{Encoding, SAMPLE_SIZE};
+use crate::utils::error::RuclifsError;
+use crate::utils::file_sys::get_files_in_path;
+use crate::utils::parallel::{default_jobs, for_each_ordered};
+use anyhow::Result;
+use clap::builder::ArgAction;
+use clap::Args;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    /// File or folder to search
+    pub path: std::path::PathBuf,
+    #[arg(short = 'f', long = "filter", default_value_t=String::from("*"))]
+    pub filter_string: String,
+    #[arg(short = 'p', long = "pattern")]
+    pub pattern: String,
+    #[arg(short = 'r', long = "recursive", action=ArgAction::SetTrue)]
+    pub recursive: bool,
+    /// Number of lines shown after each match
+    #[arg(short = 'A', long = "after")]
+    pub after: Option<usize>,
+    /// Number of lines shown before each match
+    #[arg(short = 'B', long = "before")]
+    pub before: Option<usize>,
+    /// Number of lines shown before and after each match
+    #[arg(short = 'C', long = "context", default_value_t = 0)]
+    pub context: usize,
+    /// Only print the number of matches per file
+    #[arg(short = 'c', long = "count", action=ArgAction::SetTrue)]
+    pub count: bool,
+    /// Also search files that look binary, these are skipped otherwise
+    #[arg(long = "binary", action=ArgAction::SetTrue)]
+    pub binary: bool,
+    /// Number of files searched in parallel, defaults to the number of CPUs
+    #[arg(short = 'j', long = "jobs", default_value_t = default_jobs())]
+    pub jobs: usize,
+}
+
+/// #### Search a pattern line by line while streaming from a reader.
+///
+/// ##### Arguments
+/// * `reader`: Source of the text, positioned after a potential BOM
+/// * `encoding`: Encoding of the text
+/// * `regex`: Compiled pattern to look for
+/// * `before`: Number of context lines before a matching line
+/// * `after`: Number of context lines after a matching line
+/// * `on_line`: Called in order with line number (starting at 1), line content and
+///   whether the line matches, for matching lines and their context
+/// * `return`: Number of matches
+pub fn grep_stream<R: BufRead>(
+    mut reader: R,
+    encoding: Encoding,
+    regex: &Regex,
+    before: usize,
+    after: usize,
+    mut on_line: impl FnMut(usize, &str, bool),
+) -> io::Result<usize> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_num: usize = 0;
+    let mut num_matches: usize = 0;
+    let mut lines_before: VecDeque<(usize, String)> = VecDeque::with_capacity(before + 1);
+    let mut after_left: usize = 0;
+
+    loop {
+        buffer.clear();
+        if encoding.read_line(&mut reader, &mut buffer)? == 0 {
+            break;
+        }
+        line_num += 1;
+        let (content, _) = encoding.split_line_ending(&buffer);
+        let Some(text) = encoding.decode(content) else {
+            continue;
+        };
+
+        let num_line_matches = regex.find_iter(&text).count();
+        if num_line_matches > 0 {
+            num_matches += num_line_matches;
+            for (num, line) in lines_before.drain(..) {
+                on_line(num, &line, false);
+            }
+            on_line(line_num, &text, true);
+            after_left = after;
+        } else if after_left > 0 {
+            on_line(line_num, &text, false);
+            after_left -= 1;
+        } else if before > 0 {
+            if lines_before.len() == before {
+                lines_before.pop_front();
+            }
+            lines_before.push_back((line_num, text));
+        }
+    }
+    Ok(num_matches)
+}
+
+/// #### Search a file and list matching lines with their context.
+///
+/// ##### Arguments
+/// * `out`: The listing is added here, so files can be searched in parallel
+/// * `return`: Number of matches, `None` for skipped binary files
+#[allow(clippy::too_many_arguments)]
+fn grep_file(
+    path_file: &Path,
+    regex: &Regex,
+    before: usize,
+    after: usize,
+    count_only: bool,
+    binary: bool,
+    match_styler: &Styler,
+    out: &mut String,
+) -> Result<Option<usize>> {
+    let styler_file = Styler::build("yellow", "", false, false, "").unwrap();
+    let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+    let file = File::open(path_file).map_err(RuclifsError::io(path_file))?;
+    let mut reader = BufReader::with_capacity(SAMPLE_SIZE, file);
+
+    let Some(encoding) =
+        detect_encoding(&mut reader, binary).map_err(RuclifsError::io(path_file))?
+    else {
+        return Ok(None);
+    };
+
+    let mut last_line_num: usize = 0;
+    let on_line = |line_num: usize, text: &str, is_match: bool| {
+        if count_only {
+            return;
+        }
+        if last_line_num == 0 {
+            let _ = writeln!(
+                out,
+                "{}",
+                styler_file.style(&path_file.display().to_string())
+            );
+        } else if line_num > last_line_num + 1 {
+            let _ = writeln!(out, "{INDENT}{}", styler_grayed.style("--"));
+        }
+        last_line_num = line_num;
+
+        if is_match {
+            let column = text[..regex.find(text).unwrap().start()].chars().count() + 1;
+            let _ = writeln!(
+                out,
+                "{INDENT}{}{}",
+                styler_grayed.style(format!("{:>5}:{:<4}", line_num, column).as_str()),
+                match_styler.style(text)
+            );
+        } else {
+            let _ = writeln!(
+                out,
+                "{INDENT}{}{}",
+                styler_grayed.style(format!("{:>5}-{:<4}", line_num, "").as_str()),
+                text
+            );
+        }
+    };
+    let num_matches = grep_stream(reader, encoding, regex, before, after, on_line)
+        .map_err(RuclifsError::io(path_file))?;
+
+    if count_only {
+        let _ = writeln!(
+            out,
+            "{}: {}",
+            styler_file.style(&path_file.display().to_string()),
+            num_matches
+        );
+    } else if num_matches > 0 {
+        let _ = writeln!(out, "{INDENT}{num_matches} matches");
+    }
+    Ok(Some(num_matches))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn grep(
+    path: &Path,
+    filter_string: &str,
+    pattern: &str,
+    recursive: bool,
+    before: usize,
+    after: usize,
+    count_only: bool,
+    binary: bool,
+    jobs: usize,
+) -> Result<()> {
+    let regex = Regex::new(pattern).map_err(RuclifsError::InvalidRegex)?;
+    let match_styler = Styler::build("cyan", "", false, true, pattern)?;
+
+    let files = get_files_in_path(path, filter_string, recursive, jobs)?;
+    println!("Searching {} files:", files.len());
+    print_line("");
+
+    let mut num_matches: usize = 0;
+    let mut num_files_matching: usize = 0;
+    // files that could not be searched are reported and skipped, the first error is returned
+    let mut num_files_failed: usize = 0;
+    let mut first_error: Option<anyhow::Error> = None;
+    for_each_ordered(
+        &files,
+        jobs,
+        |file| {
+            let mut out = String::new();
+            grep_file(
+                file,
+                &regex,
+                before,
+                after,
+                count_only,
+                binary,
+                &match_styler,
+                &mut out,
+            )
+            .map(|num_file_matches| (num_file_matches.unwrap_or_default(), out))
+        },
+        |result| {
+            let (num_file_matches, out) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    num_files_failed += 1;
+                    first_error.get_or_insert(e);
+                    return Ok(());
+                }
+            };
+            print!("{out}");
+            num_matches += num_file_matches;
+            if num_file_matches > 0 {
+                num_files_matching += 1;
+            }
+            Ok(())
+        },
+    )?;
+    print_line("");
+    println!("{num_matches} matches in {num_files_matching} files");
+    match first_error {
+        Some(e) => Err(e.context(format!("{num_files_failed} files could not be searched"))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test_grep {
+    use regex::Regex;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    use super::{grep_file, grep_stream};
+    use crate::utils::cli::Styler;
+    use crate::utils::encoding::Encoding;
+    use crate::utils::error::{get_exit_code, RuclifsError};
+
+    fn collect_lines(input: &str, before: usize, after: usize) -> (usize, Vec<(usize, bool)>) {
+        let regex = Regex::new("match").unwrap();
+        let mut lines: Vec<(usize, bool)> = Vec::new();
+        let num_matches = grep_stream(
+            Cursor::new(input),
+            Encoding::Utf8 { bom: false },
+            &regex,
+            before,
+            after,
+            |line_num, _, is_match| lines.push((line_num, is_match)),
+        )
+        .unwrap();
+        (num_matches, lines)
+    }
+
+    #[test]
+    fn matches_only() {
+        let (num_matches, lines) = collect_lines("a\nmatch match\nb\nmatch\n", 0, 0);
+        assert_eq!(num_matches, 3);
+        assert_eq!(lines, vec![(2, true), (4, true)]);
+    }
+
+    #[test]
+    fn with_context() {
+        let input = "a\nb\nmatch\nc\nd\ne\nf\nmatch\ng\n";
+        let (_, lines) = collect_lines(input, 1, 2);
+        assert_eq!(
+            lines,
+            vec![
+                (2, false),
+                (3, true),
+                (4, false),
+                (5, false),
+                (7, false),
+                (8, true),
+                (9, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_context() {
+        let (_, lines) = collect_lines("match\na\nmatch\nb\n", 1, 1);
+        assert_eq!(lines, vec![(1, true), (2, false), (3, true), (4, false)]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn read_error_names_file() {
+        // a folder opens like a file on unix, but reading it fails
+        let tempdir = tempdir().unwrap();
+        let regex = Regex::new("match").unwrap();
+        let styler = Styler::build("", "", false, false, "").unwrap();
+        let mut out = String::new();
+        let e = grep_file(
+            tempdir.path(),
+            &regex,
+            0,
+            0,
+            false,
+            false,
+            &styler,
+            &mut out,
+        )
+        .unwrap_err();
+        match e.downcast_ref::<RuclifsError>() {
+            Some(RuclifsError::Io { path, .. }) => assert_eq!(path, tempdir.path()),
+            _ => panic!("unexpected error: {e:#}"),
+        }
+        assert_eq!(get_exit_code(&e), 5);
+
+        tempdir.close().unwrap();
+    }
+}
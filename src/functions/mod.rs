@@ -1,3 +1,7 @@
+pub mod du;
+pub mod grep;
 pub mod rename;
 pub mod sed;
+pub mod sed_script;
 pub mod tree;
+pub mod tree_render;
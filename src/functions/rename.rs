@@ -1,12 +1,17 @@
-use crate::utils::cli::{print_line, proceed_query, Styler, INDENT};
+use crate::utils::case::replace_preserving_case;
+use crate::utils::cli::{
+    ensure_unattended, parse_selection, print_line, proceed_query, query_input, Confirmation,
+    Styler, INDENT,
+};
+use crate::utils::error::RuclifsError;
 use crate::utils::file_sys::{get_files, UniquePathGetter};
-use anyhow::Result;
+use crate::utils::parallel::default_jobs;
+use anyhow::{anyhow, Result};
 use clap::builder::ArgAction;
 use clap::Args;
 use regex::Regex;
-use std::error::Error;
-use std::path::PathBuf;
-use std::process;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
 
 #[derive(Args, Debug)]
 pub struct RenameArgs {
@@ -21,89 +26,287 @@ pub struct RenameArgs {
     pub recursive: bool,
     #[arg(short = 'S', long = "skip_preview", action=ArgAction::SetTrue)]
     pub skip_preview: bool,
+    /// Match case-insensitively and keep the casing of each match, e.g. user -> account
+    /// turns USER_ID into ACCOUNT_ID
+    #[arg(long = "preserve-case", action=ArgAction::SetTrue)]
+    pub preserve_case: bool,
 }
 
-fn rename_file(
-    path_file: &PathBuf,
-    regex: &Regex,
-    substitute: &str,
-    dry_run: bool,
-    match_styler: &Styler,
-    path_getter: &UniquePathGetter,
-) -> Result<PathBuf> {
-    let file_name = path_file.file_name().unwrap().to_str().unwrap();
-    let file_name_new = regex.replace_all(file_name, substitute).to_string();
-    let styler_warning = Styler::build("yellow", "", false, false, "").unwrap();
-    let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+/// Planned renaming of a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renaming {
+    /// Current path of the file
+    pub from: PathBuf,
+    /// Path after renaming, same as `from` if the pattern does not match
+    pub to: PathBuf,
+    /// Whether a suffix was added to `to` because the new name was taken already
+    pub suffixed: bool,
+}
 
-    if file_name_new != file_name {
-        let file_name_color = match_styler.style(file_name);
+impl Renaming {
+    pub fn is_change(&self) -> bool {
+        self.from != self.to
+    }
 
-        let path_candidate = path_file.parent().unwrap().join(file_name_new);
-        let path_new = path_getter.get_unique(&path_candidate);
+    /// Rename the file on disk, never overwriting an existing file.
+    pub fn apply(&self) -> Result<()> {
+        if !self.is_change() {
+            return Ok(());
+        }
+        if self.to.exists() {
+            return Err(RuclifsError::Io {
+                path: self.to.clone(),
+                source: io::Error::new(io::ErrorKind::AlreadyExists, "target exists already"),
+            }
+            .into());
+        }
+        std::fs::rename(&self.from, &self.to).map_err(RuclifsError::io(&self.from))?;
+        Ok(())
+    }
+}
 
-        let mut print_message = format!(
-            "{} -> {}",
-            file_name_color,
-            path_new.file_name().unwrap().to_str().unwrap()
-        );
+/// #### Builder for renaming files by a regex on their names.
+///
+/// Computing the renamings does not touch the file system, they are only executed by
+/// [`RenamePlan::execute`] or [`Renaming::apply`].
+///
+/// ```no_run
+/// use ruclifs::RenamePlan;
+///
+/// let plan = RenamePlan::new("draft_(\\d+)", "final_$1")
+///     .filter("*.md")
+///     .recursive(true);
+/// for renaming in plan.renamings(&plan.files("docs".as_ref())?)? {
+///     println!("{:?} -> {:?}", renaming.from, renaming.to);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RenamePlan {
+    pattern: String,
+    substitute: String,
+    filter: String,
+    recursive: bool,
+    preserve_case: bool,
+    threads: usize,
+}
 
-        if path_new != path_candidate {
-            print_message.push_str(INDENT);
-            print_message
-                .push_str(&styler_warning.style("Warning: Path already exists, adding suffix."))
+impl RenamePlan {
+    /// Plan replacing all matches of `pattern` in file names by `substitute`.
+    pub fn new(pattern: &str, substitute: &str) -> RenamePlan {
+        RenamePlan {
+            pattern: pattern.to_string(),
+            substitute: substitute.to_string(),
+            filter: String::from("*"),
+            recursive: false,
+            preserve_case: false,
+            threads: default_jobs(),
         }
+    }
+
+    /// Only rename files matching a glob pattern, all files by default.
+    pub fn filter(mut self, filter: &str) -> RenamePlan {
+        self.filter = filter.to_string();
+        self
+    }
 
-        println!("{}", print_message);
-        if !dry_run {
-            let _ = std::fs::rename(path_file, path_new.clone());
+    /// Rename files in subfolders as well.
+    pub fn recursive(mut self, recursive: bool) -> RenamePlan {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Match case-insensitively and keep the casing of each match.
+    pub fn preserve_case(mut self, preserve_case: bool) -> RenamePlan {
+        self.preserve_case = preserve_case;
+        self
+    }
+
+    /// Number of threads looking for files, one per CPU by default.
+    pub fn threads(mut self, threads: usize) -> RenamePlan {
+        self.threads = threads;
+        self
+    }
+
+    /// Pattern as it is matched, e.g. to highlight matches.
+    pub fn pattern(&self) -> String {
+        if self.preserve_case {
+            format!("(?i){}", self.pattern)
+        } else {
+            self.pattern.clone()
         }
-        return Ok(path_new);
-    } else {
-        let printout = styler_grayed.style(format!("{} -> {}", file_name, file_name).as_str());
+    }
+
+    /// Files in a folder the plan applies to, sorted.
+    pub fn files(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        get_files(path, &self.filter, self.recursive, self.threads)
+    }
+
+    /// #### Compute the renamings for a list of files.
+    ///
+    /// Renamings are planned in order, so a name freed by an earlier file can be taken
+    /// by a later one. Colliding names get a numbered suffix.
+    ///
+    /// ##### Arguments
+    /// * `files`: Files to rename, files not listed keep their names
+    /// * `return`: One renaming per file, in the same order, an error if a file name is
+    ///   not valid UTF-8
+    pub fn renamings(&self, files: &[PathBuf]) -> Result<Vec<Renaming>> {
+        let regex = Regex::new(&self.pattern()).map_err(RuclifsError::InvalidRegex)?;
+        // mock the new file structure, nothing is renamed yet
+        let mut path_getter = UniquePathGetter::new();
+        let mut renamings = Vec::with_capacity(files.len());
+        for file in files {
+            let file_name = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| anyhow!("{}: file name is not valid UTF-8", file.display()))?;
+            let file_name_new = if self.preserve_case {
+                replace_preserving_case(&regex, file_name, 0, &self.substitute).to_string()
+            } else {
+                regex.replace_all(file_name, &self.substitute).to_string()
+            };
+            if file_name_new == file_name {
+                renamings.push(Renaming {
+                    from: file.clone(),
+                    to: file.clone(),
+                    suffixed: false,
+                });
+                continue;
+            }
+            let path_candidate = file.parent().unwrap().join(file_name_new);
+            let path_new = path_getter.get_unique(&path_candidate);
+            path_getter.add_mock_taken(path_new.clone());
+            path_getter.add_mock_free(file.clone());
+            renamings.push(Renaming {
+                from: file.clone(),
+                suffixed: path_new != path_candidate,
+                to: path_new,
+            });
+        }
+        Ok(renamings)
+    }
 
-        println!("{printout}");
-        return Ok(path_file.to_path_buf());
+    /// Rename all files in a folder the plan applies to.
+    pub fn execute(&self, path: &Path) -> Result<Vec<Renaming>> {
+        let renamings = self.renamings(&self.files(path)?)?;
+        for renaming in &renamings {
+            renaming.apply()?;
+        }
+        Ok(renamings)
+    }
+}
+
+fn format_renaming(renaming: &Renaming, match_styler: &Styler) -> String {
+    let styler_warning = Styler::build("yellow", "", false, false, "").unwrap();
+    let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+
+    let file_name = renaming.from.file_name().unwrap().to_string_lossy();
+    if !renaming.is_change() {
+        return styler_grayed.style(format!("{} -> {}", file_name, file_name).as_str());
+    }
+    let mut message = format!(
+        "{} -> {}",
+        match_styler.style(&file_name),
+        renaming.to.file_name().unwrap().to_string_lossy()
+    );
+    if renaming.suffixed {
+        message.push_str(INDENT);
+        message.push_str(&styler_warning.style("Warning: Path already exists, adding suffix."));
     }
+    message
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn rename(
-    path: &std::path::PathBuf,
+    path: &Path,
     filter_string: &str,
     pattern: &str,
     substitute: &str,
     recursive: bool,
     skip_preview: bool,
+    preserve_case: bool,
+    confirmation: Confirmation,
 ) -> Result<()> {
-    let regex = Regex::new(pattern).unwrap_or_else(|err| {
-        println!("Problem when compiling the regex pattern: {err}");
-        process::exit(1)
-    });
-    let match_styler = Styler::build("cyan", "", false, true, pattern).unwrap();
+    let plan = RenamePlan::new(pattern, substitute)
+        .filter(filter_string)
+        .recursive(recursive)
+        .preserve_case(preserve_case);
+    let match_styler = Styler::build("cyan", "", false, true, &plan.pattern())?;
 
     // get file to rename
-    let files = get_files(path, filter_string, recursive);
+    let mut files = plan.files(path)?;
     println!("Renaming {} files:", files.len());
 
-    if !skip_preview {
-        let mut path_getter = UniquePathGetter::new();
-        print_line("PREVIEW");
-        for file in &files {
-            let path_new =
-                rename_file(file, &regex, substitute, true, &match_styler, &path_getter)?;
-            // mock new file structure after renaming
-            if &path_new != file {
-                path_getter.add_mock_taken(path_new);
-                path_getter.add_mock_free(file.to_path_buf());
+    if skip_preview {
+        ensure_unattended(confirmation)?;
+    } else {
+        let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+        let mut selected = vec![true; files.len()];
+        loop {
+            // renamings follow the current selection, excluded files keep their names
+            let files_selected: Vec<PathBuf> = files
+                .iter()
+                .zip(&selected)
+                .filter(|(_, keep)| **keep)
+                .map(|(file, _)| file.clone())
+                .collect();
+            let mut renamings = plan.renamings(&files_selected)?.into_iter();
+            print_line("PREVIEW");
+            for (num_file, file) in files.iter().enumerate() {
+                print!(
+                    "{} ",
+                    styler_grayed.style(&format!("[{:>3}]", num_file + 1))
+                );
+                if selected[num_file] {
+                    let renaming = renamings.next().unwrap();
+                    println!("{}", format_renaming(&renaming, &match_styler));
+                } else {
+                    let file_name = file.file_name().unwrap().to_string_lossy();
+                    println!(
+                        "{}",
+                        styler_grayed.style(&format!("{file_name} (excluded)"))
+                    );
+                }
             }
+            print_line("END PREVIEW");
+
+            // files can only be picked by someone answering
+            if confirmation != Confirmation::Ask || !io::stdin().is_terminal() {
+                break;
+            }
+            let input = query_input(
+                "To exclude or include files, give me their indices or ranges (e.g. -3,7-9), \
+                or just press enter to go on:",
+            )?;
+            if input.is_empty() {
+                break;
+            }
+            match parse_selection(&input, files.len()) {
+                Ok(indices) => {
+                    for idx in indices {
+                        selected[idx] = !selected[idx];
+                    }
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+        if !proceed_query(
+            "If you wanna rename for real, give me a 'yes' or 'y' now:",
+            confirmation,
+        )? {
+            return Err(RuclifsError::UserAbort.into());
         }
-        print_line("END PREVIEW");
-        proceed_query("If you wanna rename for real, give me a 'yes' or 'y' now:");
+        files = files
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(file, keep)| keep.then_some(file))
+            .collect();
     }
     print_line("");
-    let path_getter = UniquePathGetter::new();
-    for file in &files {
-        let _ = rename_file(file, &regex, substitute, false, &match_styler, &path_getter)?;
+    for renaming in plan.renamings(&files)? {
+        println!("{}", format_renaming(&renaming, &match_styler));
+        renaming.apply()?;
     }
     print_line("");
     Ok(())
@@ -112,9 +315,12 @@ pub fn rename(
 #[cfg(test)]
 mod test_rename {
     use std::fs::{create_dir, File};
+    use std::path::PathBuf;
     use tempfile::tempdir;
 
-    use super::rename;
+    use super::{rename, RenamePlan};
+    use crate::utils::cli::Confirmation;
+    use crate::utils::error::RuclifsError;
 
     #[test]
     fn rename_files_recursive() {
@@ -130,7 +336,17 @@ mod test_rename {
         create_dir(&subdir).unwrap();
         File::create(subdir.join("some_file.txt")).unwrap();
 
-        rename(&tempdir_path, "*", "some", "other", true, true).unwrap();
+        rename(
+            &tempdir_path,
+            "*",
+            "some",
+            "other",
+            true,
+            true,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
 
         assert!(!tempdir_path.join("some_file.txt").is_file());
         assert!(tempdir_path.join("other_file.txt").is_file());
@@ -156,7 +372,17 @@ mod test_rename {
         create_dir(&subdir).unwrap();
         File::create(subdir.join("some_file.txt")).unwrap();
 
-        rename(&tempdir_path, "*", "some", "other", false, true).unwrap();
+        rename(
+            &tempdir_path,
+            "*",
+            "some",
+            "other",
+            false,
+            true,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
 
         assert!(!tempdir_path.join("some_file.txt").is_file());
         assert!(tempdir_path.join("other_file.txt").is_file());
@@ -177,7 +403,17 @@ mod test_rename {
         let file_path = tempdir.path().join("some_other_file.txt");
         File::create(file_path).unwrap();
 
-        rename(&tempdir_path, "*other*", "some", "other", true, true).unwrap();
+        rename(
+            &tempdir_path,
+            "*other*",
+            "some",
+            "other",
+            true,
+            true,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
 
         assert!(tempdir_path.join("some_file.txt").is_file());
         assert!(!tempdir_path.join("other_file.txt").is_file());
@@ -187,4 +423,99 @@ mod test_rename {
 
         tempdir.close().unwrap();
     }
+
+    #[test]
+    fn rename_files_preserve_case() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        File::create(tempdir_path.join("userId.rs")).unwrap();
+        File::create(tempdir_path.join("USER_ID.rs")).unwrap();
+
+        rename(
+            &tempdir_path,
+            "*",
+            "user",
+            "account",
+            false,
+            true,
+            true,
+            Confirmation::Yes,
+        )
+        .unwrap();
+
+        assert!(tempdir_path.join("accountId.rs").is_file());
+        assert!(tempdir_path.join("ACCOUNT_ID.rs").is_file());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn rename_refused_without_preview() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        File::create(tempdir_path.join("some_file.txt")).unwrap();
+
+        let result = rename(
+            &tempdir_path,
+            "*",
+            "some",
+            "other",
+            false,
+            true,
+            false,
+            Confirmation::No,
+        );
+
+        let error = result.unwrap_err().downcast::<RuclifsError>().unwrap();
+        assert!(matches!(error, RuclifsError::UserAbort));
+        assert!(tempdir_path.join("some_file.txt").is_file());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn plan_without_renaming() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        File::create(tempdir_path.join("a_1.txt")).unwrap();
+        File::create(tempdir_path.join("b_1.txt")).unwrap();
+        File::create(tempdir_path.join("c_1.txt")).unwrap();
+        File::create(tempdir_path.join("other.rs")).unwrap();
+
+        let plan = RenamePlan::new("^[ab]_", "c_").filter("*.txt");
+        let files = plan.files(&tempdir_path).unwrap();
+        let renamings = plan.renamings(&files).unwrap();
+
+        let names: Vec<(&str, bool)> = renamings
+            .iter()
+            .map(|r| (r.to.file_name().unwrap().to_str().unwrap(), r.suffixed))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("c_2.txt", true), ("c_3.txt", true), ("c_1.txt", false)]
+        );
+        assert!(!renamings[2].is_change());
+        assert!(tempdir_path.join("a_1.txt").is_file());
+
+        plan.execute(&tempdir_path).unwrap();
+        assert!(tempdir_path.join("c_2.txt").is_file());
+        assert!(tempdir_path.join("c_3.txt").is_file());
+        assert!(!tempdir_path.join("a_1.txt").is_file());
+
+        tempdir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plan_non_utf8_name() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let file = PathBuf::from("folder").join(OsStr::from_bytes(b"some_\xFF.txt"));
+        let result = RenamePlan::new("some", "other").renamings(&[file]);
+        assert!(format!("{}", result.unwrap_err()).contains("not valid UTF-8"));
+    }
 }
@@ -1,12 +1,941 @@
-use clap::{Args, Parser, Subcommand};
+use crate::functions::sed_script::Script;
+use crate::utils::cli::{
+    ensure_terminal, ensure_unattended, print_line, proceed_query, query_choice, Confirmation,
+    Styler, INDENT,
+};
+use crate::utils::encoding::{detect, Encoding, FileKind, SAMPLE_SIZE};
+use crate::utils::error::RuclifsError;
+use crate::utils::file_sys::get_files_in_path;
+use crate::utils::parallel::{default_jobs, for_each_ordered};
+use anyhow::{anyhow, Context, Result};
+use clap::builder::ArgAction;
+use clap::Args;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Path argument that makes `sed` read from stdin and write to stdout.
+const STDIN_PATH: &str = "-";
 
 #[derive(Args, Debug)]
 pub struct SedArgs {
-    path_file: std::path::PathBuf,
-    #[arg(short = 'f', long = "filter")]
-    pattern: String,
-    #[arg(short = 's', long = "substitute")]
-    substitute: String,
-    #[arg(short = 'r', long = "recursive")]
+    /// File or folder to edit, "-" to filter stdin to stdout
+    pub path: std::path::PathBuf,
+    #[arg(short = 'f', long = "filter", default_value_t=String::from("*"))]
+    pub filter_string: String,
+    #[arg(short = 'p', long = "pattern", required_unless_present = "script")]
+    pub pattern: Option<String>,
+    #[arg(short = 's', long = "substitute", required_unless_present = "script")]
+    pub substitute: Option<String>,
+    /// Script file with sed commands to run instead of a single substitution
+    #[arg(long = "script", conflicts_with_all = ["pattern", "substitute"])]
+    pub script: Option<PathBuf>,
+    /// Match case-insensitively and keep the casing of each match, e.g. user -> account
+    /// turns USER_ID into ACCOUNT_ID
+    #[arg(long = "preserve-case", action=ArgAction::SetTrue)]
+    pub preserve_case: bool,
+    /// Ask for every changed line whether to apply it, instead of previewing all changes
+    #[arg(short = 'i', long = "interactive", action=ArgAction::SetTrue)]
+    pub interactive: bool,
+    #[arg(short = 'r', long = "recursive", action=ArgAction::SetTrue)]
+    pub recursive: bool,
+    #[arg(short = 'S', long = "skip_preview", action=ArgAction::SetTrue)]
+    pub skip_preview: bool,
+    /// Maximum number of changed lines printed per file in the preview
+    #[arg(short = 'm', long = "max_preview", default_value_t = 10)]
+    pub max_preview: usize,
+    /// Also edit files that look binary, these are skipped otherwise
+    #[arg(long = "binary", action=ArgAction::SetTrue)]
+    pub binary: bool,
+    /// Number of files processed in parallel, defaults to the number of CPUs
+    #[arg(short = 'j', long = "jobs", default_value_t = default_jobs())]
+    pub jobs: usize,
+}
+
+impl SedArgs {
+    /// Whether sed runs as a filter from stdin to stdout, where nothing else may be printed.
+    pub fn is_filter_mode(&self) -> bool {
+        self.path.as_os_str() == STDIN_PATH
+    }
+
+    /// Script to run, either read from the script file or made of pattern and substitute.
+    pub fn get_script(&self) -> Result<Script> {
+        match (&self.script, &self.pattern, &self.substitute) {
+            (Some(path_script), _, _) => Script::parse(
+                &fs::read_to_string(path_script).map_err(RuclifsError::io(path_script))?,
+                self.preserve_case,
+            ),
+            (None, Some(pattern), Some(substitute)) => {
+                Script::substitution(pattern, substitute, self.preserve_case)
+            }
+            _ => Err(anyhow!(
+                "Either a script or pattern and substitute are needed"
+            )),
+        }
+    }
+}
+
+/// Number of unchanged lines kept as context of a change.
+const CONTEXT_LINES: usize = 2;
+
+/// Line changed by a script, as passed to the callback of [`sed_stream`].
+pub struct Change<'a> {
+    /// Line number, starting at 1
+    pub line_num: usize,
+    /// Line content before the change
+    pub old: &'a str,
+    /// Lines replacing the old line, empty if it was deleted
+    pub new: &'a [String],
+    /// Up to 2 input lines before the changed line
+    pub before: &'a VecDeque<String>,
+}
+
+/// #### Run a script line by line while streaming from a reader to a writer.
+///
+/// Only a single line is held in memory at a time. Lines are decoded and written back
+/// in the given encoding, lines that can not be decoded are passed through untouched.
+/// Line endings are kept as they are, added lines get the ending of the line they were
+/// added to.
+///
+/// ##### Arguments
+/// * `reader`: Source of the text, positioned after a potential BOM
+/// * `writer`: Sink for the edited text
+/// * `encoding`: Encoding of the text
+/// * `script`: Commands to run on every line
+/// * `on_change`: Called for every changed line, the change is only written if it
+///   returns `true`
+/// * `return`: Number of changed lines written
+pub fn sed_stream<R: BufRead, W: Write>(
+    mut reader: R,
+    writer: &mut W,
+    encoding: Encoding,
+    script: &Script,
+    mut on_change: impl FnMut(&Change) -> bool,
+) -> Result<usize> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_num: usize = 0;
+    let mut num_changed: usize = 0;
+    let mut last_ending: Vec<u8> = encoding.encode("\n").unwrap();
+    let mut lines_before: VecDeque<String> = VecDeque::with_capacity(CONTEXT_LINES + 1);
+
+    loop {
+        buffer.clear();
+        if encoding.read_line(&mut reader, &mut buffer)? == 0 {
+            break;
+        }
+        line_num += 1;
+        let (content, ending) = encoding.split_line_ending(&buffer);
+        if !ending.is_empty() {
+            last_ending = ending.to_vec();
+        }
+
+        let Some(text) = encoding.decode(content) else {
+            writer.write_all(&buffer)?;
+            continue;
+        };
+        let lines_new = script.apply(&text);
+        let change = Change {
+            line_num,
+            old: &text,
+            new: &lines_new,
+            before: &lines_before,
+        };
+        if (lines_new.len() == 1 && lines_new[0] == text) || !on_change(&change) {
+            writer.write_all(&buffer)?;
+        } else {
+            num_changed += 1;
+            for (num_new, line_new) in lines_new.iter().enumerate() {
+                let Some(raw_new) = encoding.encode(line_new) else {
+                    return Err(anyhow!(
+                        "Replacement in line {line_num} can not be encoded as {encoding:?}"
+                    ));
+                };
+                writer.write_all(&raw_new)?;
+                if num_new + 1 < lines_new.len() {
+                    writer.write_all(&last_ending)?;
+                }
+            }
+            if !lines_new.is_empty() {
+                writer.write_all(ending)?;
+            }
+        }
+
+        if lines_before.len() == CONTEXT_LINES {
+            lines_before.pop_front();
+        }
+        lines_before.push_back(text);
+    }
+    writer.flush()?;
+    Ok(num_changed)
+}
+
+/// Path of the temporary output written next to the edited file.
+fn get_temp_path(path_file: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path_file.file_name().unwrap());
+    file_name.push(".ruclifs_tmp");
+    path_file.with_file_name(file_name)
+}
+
+/// #### Detect the encoding of a buffered input and skip its BOM.
+///
+/// ##### Arguments
+/// * `reader`: Input, not read from yet
+/// * `binary`: Treat binary input as UTF-8 instead of returning `None`
+/// * `return`: Encoding of the input, `None` for binary input
+pub fn detect_encoding<R: BufRead>(reader: &mut R, binary: bool) -> io::Result<Option<Encoding>> {
+    let encoding = match detect(reader.fill_buf()?) {
+        FileKind::Text(encoding) => encoding,
+        FileKind::Binary if binary => Encoding::Utf8 { bom: false },
+        FileKind::Binary => return Ok(None),
+    };
+    reader.consume(encoding.bom().len());
+    Ok(Some(encoding))
+}
+
+/// Format a change as old line with highlighted matches followed by the new lines.
+fn format_change(change: &Change, match_styler: &Styler, styler_grayed: &Styler) -> String {
+    let mut text = format!(
+        "{INDENT}{} {} -> ",
+        styler_grayed.style(format!("{:>5}:", change.line_num).as_str()),
+        match_styler.style(change.old),
+    );
+    if change.new.is_empty() {
+        let _ = writeln!(text, "{}", styler_grayed.style("[deleted]"));
+    }
+    for (num_new, line_new) in change.new.iter().enumerate() {
+        if num_new > 0 {
+            let _ = write!(text, "{INDENT}{:>6} -> ", "");
+        }
+        let _ = writeln!(text, "{line_new}");
+    }
+    text
+}
+
+/// #### Give a temporary file the owner, group and mode of the file it is to replace.
+///
+/// ##### Arguments
+/// * `path_temp`: Temporary file with the new content
+/// * `metadata`: Metadata of the file to replace
+/// * `return`: Whether the temporary file can be moved over the original, `false` if the
+///   original has further hard links or its owner can not be kept
+#[cfg(unix)]
+fn take_over_metadata(path_temp: &Path, metadata: &fs::Metadata) -> io::Result<bool> {
+    use std::os::unix::fs::{chown, MetadataExt};
+    if metadata.nlink() > 1 {
+        return Ok(false);
+    }
+    // changing the owner may clear setuid bits, so the mode is set afterwards
+    let owner_kept = chown(path_temp, Some(metadata.uid()), Some(metadata.gid())).is_ok();
+    fs::set_permissions(path_temp, metadata.permissions())?;
+    Ok(owner_kept)
+}
+
+#[cfg(not(unix))]
+fn take_over_metadata(path_temp: &Path, metadata: &fs::Metadata) -> io::Result<bool> {
+    fs::set_permissions(path_temp, metadata.permissions())?;
+    Ok(true)
+}
+
+/// Edited copy of a file, written next to it and not moved over it yet.
+///
+/// The copy is removed when dropped without being committed, so files processed ahead
+/// of an error are left untouched.
+struct StagedFile {
+    /// Path as given, used in messages
+    path: PathBuf,
+    /// Path with symlinks resolved, the file that is replaced
+    path_real: PathBuf,
+    path_temp: PathBuf,
+    num_changed: usize,
+}
+
+impl StagedFile {
+    /// #### Move the edited copy over the original file.
+    ///
+    /// Files with further hard links or an owner that can not be kept are written back
+    /// in place, so links, owner and mode stay as they are.
+    ///
+    /// ##### Arguments
+    /// * `return`: Number of changed lines written
+    fn commit(self) -> Result<usize> {
+        if self.num_changed == 0 {
+            return Ok(0);
+        }
+        let metadata = fs::metadata(&self.path_real).map_err(RuclifsError::io(&self.path_real))?;
+        if take_over_metadata(&self.path_temp, &metadata)
+            .map_err(RuclifsError::io(&self.path_temp))?
+        {
+            fs::rename(&self.path_temp, &self.path_real)
+                .map_err(RuclifsError::io(&self.path_real))?;
+            return Ok(self.num_changed);
+        }
+        let mut file_temp =
+            File::open(&self.path_temp).map_err(RuclifsError::io(&self.path_temp))?;
+        fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.path_real)
+            .and_then(|mut file_real| io::copy(&mut file_temp, &mut file_real))
+            .map_err(RuclifsError::io(&self.path_real))?;
+        Ok(self.num_changed)
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        // gone already if the copy was moved over the original
+        let _ = fs::remove_file(&self.path_temp);
+    }
+}
+
+/// #### Stream a file through a script and write the result to a temporary file.
+///
+/// The temporary file is moved over the original by [`StagedFile::commit`] once the
+/// whole file was processed, so the file keeps its encoding, BOM and line endings and
+/// is left untouched on errors. Symlinks are resolved, so the linked file is edited.
+///
+/// ##### Arguments
+/// * `on_change`: Decides for every changed line whether it is written
+fn stage_file<R: BufRead>(
+    path_file: &Path,
+    reader: R,
+    encoding: Encoding,
+    script: &Script,
+    on_change: impl FnMut(&Change) -> bool,
+) -> Result<StagedFile> {
+    let path_real = fs::canonicalize(path_file).map_err(RuclifsError::io(path_file))?;
+    let path_temp = get_temp_path(&path_real);
+    let file_temp = File::create(&path_temp).map_err(RuclifsError::io(&path_temp))?;
+    let mut staged = StagedFile {
+        path: path_file.to_path_buf(),
+        path_real,
+        path_temp,
+        num_changed: 0,
+    };
+    let mut writer = BufWriter::new(file_temp);
+    writer
+        .write_all(encoding.bom())
+        .map_err(RuclifsError::io(&staged.path_temp))?;
+    staged.num_changed = sed_stream(reader, &mut writer, encoding, script, on_change)
+        .with_context(|| path_file.display().to_string())?;
+    Ok(staged)
+}
+
+/// #### Stream a file through a script into an edited copy.
+///
+/// Nothing is written when running dry, the preview is added to `out` instead, so
+/// files can be processed in parallel and printed in order. Otherwise the edited copy
+/// is returned, to be committed in order once all files before it were written.
+fn sed_file(
+    path_file: &Path,
+    script: &Script,
+    dry_run: bool,
+    max_preview: usize,
+    binary: bool,
+    match_styler: &Styler,
+    out: &mut String,
+) -> Result<Option<StagedFile>> {
+    let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+    let file = File::open(path_file).map_err(RuclifsError::io(path_file))?;
+    let mut reader = BufReader::with_capacity(SAMPLE_SIZE, file);
+
+    let Some(encoding) =
+        detect_encoding(&mut reader, binary).map_err(RuclifsError::io(path_file))?
+    else {
+        if dry_run {
+            let _ = writeln!(
+                out,
+                "{}",
+                styler_grayed
+                    .style(format!("{}: binary file, skipping", path_file.display()).as_str())
+            );
+        }
+        return Ok(None);
+    };
+
+    if dry_run {
+        let mut printed: usize = 0;
+        let on_change = |change: &Change| {
+            if printed == 0 {
+                let _ = writeln!(out, "{}", path_file.display());
+            }
+            if printed < max_preview {
+                out.push_str(&format_change(change, match_styler, &styler_grayed));
+            }
+            printed += 1;
+            true
+        };
+        let num_changed = sed_stream(reader, &mut io::sink(), encoding, script, on_change)
+            .with_context(|| path_file.display().to_string())?;
+        if num_changed > max_preview {
+            let _ = writeln!(
+                out,
+                "{INDENT}{}",
+                styler_grayed.style(
+                    format!("... and {} more changed lines", num_changed - max_preview).as_str()
+                )
+            );
+        }
+        return Ok(None);
+    }
+
+    stage_file(path_file, reader, encoding, script, |_| true).map(Some)
+}
+
+/// #### Stream a file through a script, asking for every changed line whether to apply it.
+///
+/// ##### Arguments
+/// * `quit`: Set when the user chose to quit, no further changes are applied then
+/// * `query`: Asks for the choice on a change, one of `ynaq`
+/// * `return`: Number of changed lines written, the file stays untouched if a query failed
+fn sed_file_interactive(
+    path_file: &Path,
+    script: &Script,
+    binary: bool,
+    match_styler: &Styler,
+    quit: &mut bool,
+    mut query: impl FnMut() -> Result<char>,
+) -> Result<usize> {
+    let styler_file = Styler::build("yellow", "", false, false, "").unwrap();
+    let styler_grayed = Styler::build("gray", "", false, false, "").unwrap();
+    let file = File::open(path_file).map_err(RuclifsError::io(path_file))?;
+    let mut reader = BufReader::with_capacity(SAMPLE_SIZE, file);
+
+    let Some(encoding) =
+        detect_encoding(&mut reader, binary).map_err(RuclifsError::io(path_file))?
+    else {
+        return Ok(0);
+    };
+
+    let mut all_in_file = false;
+    let mut query_error: Option<anyhow::Error> = None;
+    let on_change = |change: &Change| {
+        if *quit || query_error.is_some() {
+            return false;
+        }
+        if all_in_file {
+            return true;
+        }
+        println!("{}", styler_file.style(&path_file.display().to_string()));
+        let first_context_line = change.line_num - change.before.len();
+        for (num, line) in change.before.iter().enumerate() {
+            let line_num = format!("{:>5}-", first_context_line + num);
+            println!("{INDENT}{} {}", styler_grayed.style(&line_num), line);
+        }
+        print!("{}", format_change(change, match_styler, &styler_grayed));
+
+        match query() {
+            Ok('y') => true,
+            Ok('a') => {
+                all_in_file = true;
+                true
+            }
+            Ok('q') => {
+                *quit = true;
+                false
+            }
+            Ok(_) => false,
+            Err(e) => {
+                query_error = Some(e);
+                false
+            }
+        }
+    };
+    let staged = stage_file(path_file, reader, encoding, script, on_change)?;
+    if let Some(e) = query_error {
+        // dropping the staged file removes it, a half answered file is not written
+        return Err(e);
+    }
+    staged.commit()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn sed(
+    path: &Path,
+    filter_string: &str,
+    script: &Script,
     recursive: bool,
+    skip_preview: bool,
+    max_preview: usize,
+    binary: bool,
+    jobs: usize,
+    interactive: bool,
+    confirmation: Confirmation,
+) -> Result<()> {
+    // filter mode: no preview, no query, only the edited text goes to stdout
+    if path.as_os_str() == STDIN_PATH {
+        if interactive {
+            return Err(anyhow!(
+                "Interactive mode needs stdin, it can not filter stdin"
+            ));
+        }
+        let mut reader = io::stdin().lock();
+        let mut writer = BufWriter::new(io::stdout().lock());
+        let encoding = detect_encoding(&mut reader, true)?.unwrap();
+        writer.write_all(encoding.bom())?;
+        sed_stream(reader, &mut writer, encoding, script, |_| true)?;
+        return Ok(());
+    }
+
+    let match_styler = Styler::build("cyan", "", false, true, &script.highlight_pattern())?;
+
+    // get files to edit
+    let files = get_files_in_path(path, filter_string, recursive, jobs)?;
+    println!("Editing {} files:", files.len());
+
+    if interactive {
+        if confirmation != Confirmation::Ask {
+            return Err(anyhow!(
+                "Interactive mode can not be combined with --yes or --no"
+            ));
+        }
+        ensure_terminal()?;
+        let mut quit = false;
+        for file in &files {
+            let num_changed =
+                sed_file_interactive(file, script, binary, &match_styler, &mut quit, || {
+                    query_choice("Apply this change [y]es/[n]o/[a]ll in file/[q]uit?", "ynaq")
+                })?;
+            if num_changed > 0 {
+                println!("{}: {} lines changed", file.display(), num_changed);
+            }
+            if quit {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let process_files = |dry_run: bool| {
+        for_each_ordered(
+            &files,
+            jobs,
+            |file| {
+                let mut out = String::new();
+                sed_file(
+                    file,
+                    script,
+                    dry_run,
+                    max_preview,
+                    binary,
+                    &match_styler,
+                    &mut out,
+                )
+                .map(|staged| (out, staged))
+            },
+            |result| {
+                let (out, staged) = result?;
+                print!("{}", out);
+                if let Some(staged) = staged {
+                    let path = staged.path.clone();
+                    let num_changed = staged.commit()?;
+                    if num_changed > 0 {
+                        println!("{}: {} lines changed", path.display(), num_changed);
+                    }
+                }
+                Ok(())
+            },
+        )
+    };
+
+    if skip_preview {
+        ensure_unattended(confirmation)?;
+    } else {
+        print_line("PREVIEW");
+        process_files(true)?;
+        print_line("END PREVIEW");
+        if !proceed_query(
+            "If you wanna edit for real, give me a 'yes' or 'y' now:",
+            confirmation,
+        )? {
+            return Err(RuclifsError::UserAbort.into());
+        }
+    }
+    print_line("");
+    process_files(false)?;
+    print_line("");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_sed {
+    use anyhow::anyhow;
+    use std::fs::{self, create_dir};
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    use super::{sed, sed_file_interactive, sed_stream};
+    use crate::functions::sed_script::Script;
+    use crate::utils::cli::{Confirmation, Styler};
+    use crate::utils::encoding::Encoding;
+    use crate::utils::error::RuclifsError;
+
+    #[test]
+    fn stream_keeps_line_endings() {
+        let script = Script::substitution("some", "a", false).unwrap();
+        let input = "some line\r\nother line\nsome last line";
+        let mut output: Vec<u8> = Vec::new();
+        let mut changes: Vec<usize> = Vec::new();
+
+        let num_changed = sed_stream(
+            Cursor::new(input),
+            &mut output,
+            Encoding::Utf8 { bom: false },
+            &script,
+            |change| {
+                changes.push(change.line_num);
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(num_changed, 2);
+        assert_eq!(changes, vec![1, 3]);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a line\r\nother line\na last line"
+        );
+    }
+
+    #[test]
+    fn stream_capture_groups() {
+        let script = Script::substitution(r"v(\d+)\.(\d+)", "v$1.${2}1", false).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+
+        sed_stream(
+            Cursor::new("version v1.2\n"),
+            &mut output,
+            Encoding::Utf8 { bom: false },
+            &script,
+            |_| true,
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "version v1.21\n");
+    }
+
+    #[test]
+    fn stream_script_adds_and_deletes_lines() {
+        let script =
+            Script::parse("/^#/d\n/^version/i [package]\n/^version/a edition", false).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let mut changes: Vec<(usize, usize)> = Vec::new();
+
+        let num_changed = sed_stream(
+            Cursor::new("# comment\r\nversion\r\nname\r\nversion"),
+            &mut output,
+            Encoding::Utf8 { bom: false },
+            &script,
+            |change| {
+                changes.push((change.line_num, change.new.len()));
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(num_changed, 3);
+        assert_eq!(changes, vec![(1, 0), (2, 3), (4, 3)]);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[package]\r\nversion\r\nedition\r\nname\r\n[package]\r\nversion\r\nedition"
+        );
+    }
+
+    #[test]
+    fn stream_skips_rejected_changes() {
+        let script = Script::substitution("some", "a", false).unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        let mut contexts: Vec<Vec<String>> = Vec::new();
+
+        let num_changed = sed_stream(
+            Cursor::new("some 1\nother\nsome 2\nsome 3\n"),
+            &mut output,
+            Encoding::Utf8 { bom: false },
+            &script,
+            |change| {
+                contexts.push(change.before.iter().cloned().collect());
+                change.line_num != 3
+            },
+        )
+        .unwrap();
+
+        assert_eq!(num_changed, 2);
+        assert_eq!(
+            contexts,
+            vec![
+                vec![],
+                vec!["some 1".to_string(), "other".to_string()],
+                vec!["other".to_string(), "some 2".to_string()]
+            ]
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a 1\nother\nsome 2\na 3\n"
+        );
+    }
+
+    #[test]
+    fn sed_files_recursive() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        fs::write(tempdir_path.join("some_file.txt"), "some text\nnothing\n").unwrap();
+        let subdir = tempdir_path.join("subdir");
+        create_dir(&subdir).unwrap();
+        fs::write(subdir.join("some_file.rs"), "some code\n").unwrap();
+
+        sed(
+            &tempdir_path,
+            "*.txt",
+            &Script::substitution("some", "other", false).unwrap(),
+            true,
+            true,
+            10,
+            false,
+            1,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(tempdir_path.join("some_file.txt")).unwrap(),
+            "other text\nnothing\n"
+        );
+        assert_eq!(
+            fs::read_to_string(subdir.join("some_file.rs")).unwrap(),
+            "some code\n"
+        );
+        assert!(!tempdir_path.join(".some_file.txt.ruclifs_tmp").exists());
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn sed_single_file() {
+        let tempdir = tempdir().unwrap();
+        let file_path = tempdir.path().join("some_file.txt");
+        fs::write(&file_path, "some text").unwrap();
+
+        sed(
+            &file_path,
+            "*",
+            &Script::substitution("(some) text", "$1 other", false).unwrap(),
+            false,
+            true,
+            10,
+            false,
+            1,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "some other");
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn interactive_failed_query_keeps_file() {
+        let tempdir = tempdir().unwrap();
+        let file_path = tempdir.path().join("some_file.txt");
+        fs::write(&file_path, "a\na\na\n").unwrap();
+        let script = Script::substitution("a", "b", false).unwrap();
+        let match_styler = Styler::build("", "", false, false, "").unwrap();
+
+        // the first change is accepted, then the terminal goes away
+        let mut answers = vec![Ok('y'), Err(anyhow!("closed"))].into_iter();
+        let mut quit = false;
+        let res =
+            sed_file_interactive(&file_path, &script, false, &match_styler, &mut quit, || {
+                answers.next().unwrap()
+            });
+        assert!(res.is_err());
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "a\na\na\n");
+        assert_eq!(fs::read_dir(tempdir.path()).unwrap().count(), 1);
+
+        let mut quit = false;
+        let num_changed =
+            sed_file_interactive(&file_path, &script, false, &match_styler, &mut quit, || {
+                Ok('y')
+            })
+            .unwrap();
+        assert_eq!(num_changed, 3);
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "b\nb\nb\n");
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn sed_stops_at_failed_file() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        fs::write(tempdir_path.join("a.txt"), "some text\n").unwrap();
+        // the replacement can not be encoded as Latin-1
+        fs::write(tempdir_path.join("b.txt"), b"some \xFCber\n").unwrap();
+        fs::write(tempdir_path.join("c.txt"), "some text\n").unwrap();
+
+        let result = sed(
+            &tempdir_path,
+            "*",
+            &Script::substitution("some", "漢字", false).unwrap(),
+            false,
+            true,
+            10,
+            false,
+            4,
+            false,
+            Confirmation::Yes,
+        );
+
+        assert!(format!("{:#}", result.unwrap_err()).contains("b.txt"));
+        assert_eq!(
+            fs::read_to_string(tempdir_path.join("a.txt")).unwrap(),
+            "漢字 text\n"
+        );
+        assert_eq!(
+            fs::read(tempdir_path.join("b.txt")).unwrap(),
+            b"some \xFCber\n"
+        );
+        assert_eq!(
+            fs::read_to_string(tempdir_path.join("c.txt")).unwrap(),
+            "some text\n"
+        );
+        assert_eq!(fs::read_dir(&tempdir_path).unwrap().count(), 3);
+
+        tempdir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sed_keeps_links_and_mode() {
+        use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        let target = tempdir_path.join("target.sh");
+        fs::write(&target, "some script\n").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o750)).unwrap();
+        symlink("target.sh", tempdir_path.join("link.sh")).unwrap();
+        fs::write(tempdir_path.join("shared.txt"), "some text\n").unwrap();
+        fs::hard_link(
+            tempdir_path.join("shared.txt"),
+            tempdir_path.join("other.txt"),
+        )
+        .unwrap();
+        let inode = fs::metadata(tempdir_path.join("shared.txt")).unwrap().ino();
+
+        for name in ["link.sh", "shared.txt"] {
+            sed(
+                &tempdir_path.join(name),
+                "*",
+                &Script::substitution("some", "other", false).unwrap(),
+                false,
+                true,
+                10,
+                false,
+                1,
+                false,
+                Confirmation::Yes,
+            )
+            .unwrap();
+        }
+
+        let link = tempdir_path.join("link.sh");
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "other script\n");
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(
+            fs::read_to_string(tempdir_path.join("other.txt")).unwrap(),
+            "other text\n"
+        );
+        let metadata = fs::metadata(tempdir_path.join("shared.txt")).unwrap();
+        assert_eq!((metadata.ino(), metadata.nlink()), (inode, 2));
+        assert_eq!(fs::read_dir(&tempdir_path).unwrap().count(), 4);
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn sed_refused_without_preview() {
+        let tempdir = tempdir().unwrap();
+        let file_path = tempdir.path().join("some_file.txt");
+        fs::write(&file_path, "some text").unwrap();
+
+        let result = sed(
+            &file_path,
+            "*",
+            &Script::substitution("some", "other", false).unwrap(),
+            false,
+            true,
+            10,
+            false,
+            1,
+            false,
+            Confirmation::No,
+        );
+
+        let error = result.unwrap_err().downcast::<RuclifsError>().unwrap();
+        assert!(matches!(error, RuclifsError::UserAbort));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "some text");
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn sed_keeps_encoding() {
+        let tempdir = tempdir().unwrap();
+        let tempdir_path = tempdir.path().to_path_buf();
+
+        let mut utf16: Vec<u8> = vec![0xFF, 0xFE];
+        utf16.extend(
+            "some text\r\nsome more\r\n"
+                .encode_utf16()
+                .flat_map(|u| u.to_le_bytes()),
+        );
+        fs::write(tempdir_path.join("utf16.txt"), &utf16).unwrap();
+        fs::write(tempdir_path.join("latin1.txt"), b"some \xFCber\n").unwrap();
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rsome";
+        fs::write(tempdir_path.join("image.png"), png).unwrap();
+
+        sed(
+            &tempdir_path,
+            "*",
+            &Script::substitution("some", "öther", false).unwrap(),
+            false,
+            true,
+            10,
+            false,
+            2,
+            false,
+            Confirmation::Yes,
+        )
+        .unwrap();
+
+        let mut utf16_exp: Vec<u8> = vec![0xFF, 0xFE];
+        utf16_exp.extend(
+            "öther text\r\nöther more\r\n"
+                .encode_utf16()
+                .flat_map(|u| u.to_le_bytes()),
+        );
+        assert_eq!(fs::read(tempdir_path.join("utf16.txt")).unwrap(), utf16_exp);
+        assert_eq!(
+            fs::read(tempdir_path.join("latin1.txt")).unwrap(),
+            b"\xF6ther \xFCber\n"
+        );
+        assert_eq!(fs::read(tempdir_path.join("image.png")).unwrap(), png);
+
+        tempdir.close().unwrap();
+    }
 }
@@ -0,0 +1,323 @@
+use crate::utils::case::replace_preserving_case;
+use crate::utils::error::RuclifsError;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+
+/// Single edit of a sed script.
+#[derive(Debug)]
+enum Command {
+    /// Replace the pattern, all occurrences if `global` is set, otherwise the first
+    Substitute {
+        regex: Regex,
+        substitute: String,
+        global: bool,
+        preserve_case: bool,
+    },
+    /// Drop the line
+    Delete,
+    /// Add a line before the line
+    Insert(String),
+    /// Add a line after the line
+    Append(String),
+    /// Replace the whole line
+    Change(String),
+}
+
+/// Command that is only applied to lines matching its address, if it has one.
+#[derive(Debug)]
+struct Step {
+    address: Option<Regex>,
+    command: Command,
+}
+
+/// #### Sequence of commands applied to every line.
+///
+/// Scripts are written one command per line, optionally preceded by an address
+/// `/REGEX/` restricting it to matching lines. Empty lines and lines starting with
+/// `#` are ignored. Supported commands:
+/// * `s/PATTERN/SUBSTITUTE/FLAGS`: substitute, flags `g` (all occurrences) and
+///   `i` (case-insensitive), any delimiter may be used instead of `/`
+/// * `d`: delete the line
+/// * `i TEXT`: insert a line before
+/// * `a TEXT`: append a line after
+/// * `c TEXT`: change the line
+///
+/// Patterns use Rust regex syntax and substitutes may reference groups as `$1`.
+/// With `preserve_case`, substitutions match case-insensitively and adapt the casing
+/// of the substitute to each matched text.
+#[derive(Debug)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    /// Script replacing all occurrences of a pattern, as done by `sed -p PATTERN -s SUBSTITUTE`.
+    pub fn substitution(pattern: &str, substitute: &str, preserve_case: bool) -> Result<Script> {
+        Ok(Script {
+            steps: vec![Step {
+                address: None,
+                command: Command::Substitute {
+                    regex: compile(pattern, preserve_case)?,
+                    substitute: substitute.to_string(),
+                    global: true,
+                    preserve_case,
+                },
+            }],
+        })
+    }
+
+    /// Parse a script from text, see [`Script`] for the syntax.
+    pub fn parse(text: &str, preserve_case: bool) -> Result<Script> {
+        let mut steps: Vec<Step> = Vec::new();
+        for (num_line, line) in text.lines().enumerate() {
+            // trailing whitespace is kept, it may be part of the text of i, a and c
+            let line = line.trim_start();
+            if line.trim_end().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_step(line, preserve_case)
+                .with_context(|| format!("Invalid script line {}", num_line + 1))?;
+            steps.push(step);
+        }
+        Ok(Script { steps })
+    }
+
+    /// Pattern matching everything the script looks for, used to highlight lines.
+    pub fn highlight_pattern(&self) -> String {
+        // each pattern is grouped, so its inline flags do not apply to the others
+        let patterns: Vec<String> = self
+            .steps
+            .iter()
+            .filter_map(|step| match &step.command {
+                Command::Substitute { regex, .. } => Some(regex.as_str()),
+                _ => step.address.as_ref().map(|r| r.as_str()),
+            })
+            .map(|pattern| format!("(?:{pattern})"))
+            .collect();
+        patterns.join("|")
+    }
+
+    /// #### Run the script on a single line.
+    ///
+    /// ##### Arguments
+    /// * `line`: Line content without line ending
+    /// * `return`: Lines replacing the input line, may be empty
+    pub fn apply(&self, line: &str) -> Vec<String> {
+        let mut before: Vec<String> = Vec::new();
+        let mut after: Vec<String> = Vec::new();
+        let mut current: Option<String> = Some(line.to_string());
+
+        for step in &self.steps {
+            let Some(text) = current.as_ref() else {
+                break;
+            };
+            if let Some(address) = &step.address {
+                if !address.is_match(text) {
+                    continue;
+                }
+            }
+            match &step.command {
+                Command::Substitute {
+                    regex,
+                    substitute,
+                    global,
+                    preserve_case,
+                } => {
+                    let limit = if *global { 0 } else { 1 };
+                    let text_new = if *preserve_case {
+                        replace_preserving_case(regex, text, limit, substitute)
+                    } else {
+                        regex.replacen(text, limit, substitute.as_str())
+                    };
+                    current = Some(text_new.into_owned());
+                }
+                Command::Delete => current = None,
+                Command::Insert(insert) => before.push(insert.clone()),
+                Command::Append(append) => after.push(append.clone()),
+                Command::Change(change) => {
+                    before.push(change.clone());
+                    current = None;
+                }
+            }
+        }
+        before.extend(current);
+        before.extend(after);
+        before
+    }
+}
+
+fn compile(pattern: &str, case_insensitive: bool) -> Result<Regex> {
+    // inline flag, so the pattern used for highlighting is case-insensitive as well
+    let pattern = if case_insensitive {
+        format!("(?i){pattern}")
+    } else {
+        pattern.to_string()
+    };
+    Ok(Regex::new(&pattern).map_err(RuclifsError::InvalidRegex)?)
+}
+
+/// #### Split text at the first unescaped delimiter.
+///
+/// Escaped delimiters are unescaped, all other escapes are kept for the regex.
+fn split_delimited(text: &str, delimiter: char) -> Option<(String, &str)> {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            }
+        } else if c == delimiter {
+            return Some((part, &text[idx + c.len_utf8()..]));
+        } else {
+            part.push(c);
+        }
+    }
+    None
+}
+
+fn parse_step(line: &str, preserve_case: bool) -> Result<Step> {
+    let (address, rest) = match line.strip_prefix('/') {
+        Some(rest) => {
+            let (pattern, rest) =
+                split_delimited(rest, '/').ok_or_else(|| anyhow!("unterminated address"))?;
+            (Some(compile(&pattern, false)?), rest.trim_start())
+        }
+        None => (None, line),
+    };
+
+    let mut chars = rest.chars();
+    let command = match chars.next() {
+        Some('s') => {
+            let delimiter = chars
+                .next()
+                .ok_or_else(|| anyhow!("missing pattern of substitution"))?;
+            let (pattern, rest) = split_delimited(chars.as_str(), delimiter)
+                .ok_or_else(|| anyhow!("unterminated pattern of substitution"))?;
+            let (substitute, flags) = split_delimited(rest, delimiter)
+                .ok_or_else(|| anyhow!("unterminated substitute"))?;
+            let mut global = false;
+            let mut case_insensitive = false;
+            for flag in flags.trim().chars() {
+                match flag {
+                    'g' => global = true,
+                    'i' => case_insensitive = true,
+                    _ => return Err(anyhow!("unknown flag '{flag}'")),
+                }
+            }
+            Command::Substitute {
+                regex: compile(&pattern, case_insensitive || preserve_case)?,
+                substitute,
+                global,
+                preserve_case,
+            }
+        }
+        Some('d') if chars.as_str().trim().is_empty() => Command::Delete,
+        Some(c @ ('i' | 'a' | 'c')) => {
+            let text = chars.as_str();
+            // accept GNU style "a\ TEXT" as well as "a TEXT", whitespace after the
+            // single separator belongs to the text
+            let text = text.strip_prefix('\\').unwrap_or(text);
+            let text = text.strip_prefix([' ', '\t']).unwrap_or(text).to_string();
+            match c {
+                'i' => Command::Insert(text),
+                'a' => Command::Append(text),
+                _ => Command::Change(text),
+            }
+        }
+        Some(c) => return Err(anyhow!("unknown command '{c}'")),
+        None => return Err(anyhow!("missing command")),
+    };
+    Ok(Step { address, command })
+}
+
+#[cfg(test)]
+mod test_script {
+    use super::Script;
+
+    #[test]
+    fn substitute() {
+        let script = Script::parse("s/o/0/\ns|(l+)|<$1>|g", false).unwrap();
+        assert_eq!(script.apply("hello world"), vec!["he<ll>0 wor<l>d"]);
+    }
+
+    #[test]
+    fn substitute_flags_and_escapes() {
+        let script = Script::parse(r"s/A\/B/a\/b/gi", false).unwrap();
+        assert_eq!(script.apply("a/b and A/B"), vec!["a/b and a/b"]);
+    }
+
+    #[test]
+    fn delete() {
+        let script = Script::parse("/^#/d", false).unwrap();
+        assert!(script.apply("# comment").is_empty());
+        assert_eq!(script.apply("code"), vec!["code"]);
+    }
+
+    #[test]
+    fn insert_append_change() {
+        let script = Script::parse(
+            "# header handling\n\
+             /^version/i\\ [package]\n\
+             /^version/a edition = 2021\n\
+             /^name/c name = \"other\"",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            script.apply("version = 1"),
+            vec!["[package]", "version = 1", "edition = 2021"]
+        );
+        assert_eq!(script.apply("name = \"some\""), vec!["name = \"other\""]);
+        assert_eq!(script.apply("other"), vec!["other"]);
+    }
+
+    #[test]
+    fn commands_see_previous_edits() {
+        let script = Script::parse("s/0\\.1\\.0/0.2.0/\n/0\\.2\\.0/a bumped", false).unwrap();
+        assert_eq!(script.apply("v0.1.0"), vec!["v0.2.0", "bumped"]);
+    }
+
+    #[test]
+    fn preserve_case() {
+        let script = Script::parse("s/user/account/g\n/ACCOUNT/a found", true).unwrap();
+        assert_eq!(
+            script.apply("userId UserId USER_ID"),
+            vec!["accountId AccountId ACCOUNT_ID", "found"]
+        );
+        let script = Script::substitution("user", "account", true).unwrap();
+        assert_eq!(script.apply("User"), vec!["Account"]);
+        assert_eq!(script.highlight_pattern(), "(?:(?i)user)");
+    }
+
+    #[test]
+    fn text_keeps_indentation() {
+        let script =
+            Script::parse("/^\\[deps\\]/a     serde = \"1\"\n/^x/c\\   y ", false).unwrap();
+        assert_eq!(script.apply("[deps]"), vec!["[deps]", "    serde = \"1\""]);
+        assert_eq!(script.apply("x"), vec!["  y "]);
+    }
+
+    #[test]
+    fn highlight_flags_stay_local() {
+        let script = Script::parse("s/user/account/i\n/^ID/d", false).unwrap();
+        let regex = regex::Regex::new(&script.highlight_pattern()).unwrap();
+        assert!(regex.is_match("USER"));
+        assert!(regex.is_match("ID"));
+        assert!(!regex.is_match("id"));
+    }
+
+    #[test]
+    fn invalid_script() {
+        assert!(Script::parse("x", false).is_err());
+        assert!(Script::parse("s/a/b", false).is_err());
+        assert!(Script::parse("s/a/b/q", false).is_err());
+        assert!(Script::parse("/a", false).is_err());
+        assert!(Script::parse("s/(/b/", false).is_err());
+    }
+}
@@ -1,291 +1,1146 @@
-use crate::utils::cli::{bites2str, Styler};
+use crate::functions::tree_render::{counts_text, Column, RenderOptions, TreeFormat, LONG_COLUMNS};
+use crate::utils::error::RuclifsError;
+use crate::utils::parallel::{default_jobs, walk_parallel};
+use anyhow::Result;
 use clap::builder::ArgAction;
-use clap::Args;
+use clap::{Args, ValueEnum};
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::env::current_dir;
-use std::fmt;
-use std::fs::{self, metadata};
-use std::path::PathBuf;
-
-const ELBOW: &str = "└── ";
-const TEE: &str = "├── ";
-const PIPE_PREFIX: &str = "│   ";
-const SPACE_PREFIX: &str = "    ";
+use std::fs::{self, metadata, read_link, symlink_metadata, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Args, Debug)]
 pub struct TreeArgs {
     #[arg(default_value = ".")]
     pub path: std::path::PathBuf,
-    #[arg(short = 'd', long = "depth", default_value = "-1")]
+    /// Deepest level listed, 0 for the folder only and negative for no limit
+    #[arg(
+        short = 'd',
+        long = "depth",
+        default_value = "-1",
+        allow_negative_numbers = true
+    )]
     pub depth: i32,
     #[arg(short = 's', long = "hide_size", action=ArgAction::SetTrue)]
     pub hide_size: bool,
+    /// Show the size allocated on disk next to the apparent size, hard links are
+    /// counted once
+    #[arg(long = "disk-usage", action=ArgAction::SetTrue)]
+    pub disk_usage: bool,
+    /// Output format
+    #[arg(long = "format", value_enum, default_value_t = TreeFormat::Ascii)]
+    pub format: TreeFormat,
+    /// Order of entries within a folder, size and mtime list the biggest and newest first
+    #[arg(long = "sort", value_enum, default_value_t = SortKey::Name)]
+    pub sort: SortKey,
+    /// Reverse the order of entries
+    #[arg(long = "reverse", action=ArgAction::SetTrue)]
+    pub reverse: bool,
+    /// List folders before files
+    #[arg(long = "dirs-first", action=ArgAction::SetTrue, conflicts_with = "dirs_last")]
+    pub dirs_first: bool,
+    /// List folders after files, the default
+    #[arg(long = "dirs-last", action=ArgAction::SetTrue)]
+    pub dirs_last: bool,
+    /// Number of threads reading folders
+    #[arg(short = 'j', long = "threads", default_value_t = default_jobs())]
+    pub threads: usize,
+    /// Follow symbolic links to folders, links back to a parent folder are not followed
+    #[arg(long = "follow", action=ArgAction::SetTrue)]
+    pub follow: bool,
+    /// Long listing, the same as --columns perm,owner,group,size,mtime
+    #[arg(short = 'l', long = "long", action=ArgAction::SetTrue)]
+    pub long: bool,
+    /// Only list entries whose name matches, e.g. '*.rs', matching folders keep all their
+    /// content and folders leading to matches are kept
+    #[arg(short = 'P', long = "pattern")]
+    pub patterns: Vec<String>,
+    /// Leave out entries whose name matches, folders with all their content
+    #[arg(short = 'I', long = "ignore")]
+    pub ignores: Vec<String>,
+    /// Patterns are regular expressions matching part of the name instead of globs
+    #[arg(long = "regex", action=ArgAction::SetTrue)]
+    pub regex: bool,
+    /// List folders only, their sizes still cover their files
+    #[arg(short = 'D', long = "dirs-only", action=ArgAction::SetTrue)]
+    pub dirs_only: bool,
+    /// Leave out folders that have no content left after filtering
+    #[arg(long = "prune", action=ArgAction::SetTrue)]
+    pub prune: bool,
+    /// Count files and folders below each folder and end with a summary
+    #[arg(long = "count", action=ArgAction::SetTrue)]
+    pub count: bool,
+    /// Metadata listed in columns before the tree, e.g. size,mtime,perm
+    #[arg(
+        long = "columns",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "long"
+    )]
+    pub columns: Vec<Column>,
 }
 
-trait Size {
-    fn get_size(&self) -> i32;
-    //get size in bites
+impl TreeArgs {
+    fn get_columns(&self) -> Vec<Column> {
+        if self.long {
+            LONG_COLUMNS.to_vec()
+        } else {
+            self.columns.clone()
+        }
+    }
+
+    pub fn get_scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            th_depth: self.depth,
+            // sizes are needed to sort by them, even if they are not shown
+            read_size: !self.hide_size
+                || self.sort == SortKey::Size
+                || self.get_columns().contains(&Column::Size),
+            disk_usage: self.disk_usage,
+            count: self.count,
+            threads: self.threads,
+            follow: self.follow,
+        }
+    }
+
+    pub fn get_filter(&self) -> Result<TreeFilter> {
+        Ok(TreeFilter {
+            include: names_regex(&self.patterns, self.regex)?,
+            exclude: names_regex(&self.ignores, self.regex)?,
+            dirs_only: self.dirs_only,
+            prune: self.prune,
+        })
+    }
+
+    pub fn get_render_options(&self) -> RenderOptions {
+        let columns = self.get_columns();
+        RenderOptions {
+            th_depth: self.depth,
+            // sizes in a column are not repeated after the name
+            show_size: !self.hide_size && !columns.contains(&Column::Size),
+            disk_usage: self.disk_usage,
+            show_count: self.count,
+            dirs_first: self.dirs_first,
+            dirs_only: self.dirs_only,
+            columns,
+            highlight: names_regex(&self.patterns, self.regex)
+                .ok()
+                .flatten()
+                .map(|regex| regex.as_str().to_string()),
+        }
+    }
 }
 
-#[derive(Clone)]
-struct FileEntry<'a> {
-    path: PathBuf,
-    prefix: String,
-    connector: String,
-    show_size: bool,
-    size: Option<u64>,
-    styler_size: &'a Styler,
+/// What to read when scanning a tree, see [`scan`].
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Deepest level listed, negative for no limit
+    pub th_depth: i32,
+    /// Whether to read file sizes and sum them up for folders, content below
+    /// `th_depth` is only measured, no entries are built for it
+    pub read_size: bool,
+    /// Whether to read allocated sizes as well and count hard links only once
+    pub disk_usage: bool,
+    /// Whether to count files and folders below each folder, content below `th_depth`
+    /// is counted without building entries
+    pub count: bool,
+    /// Number of threads reading folders, 0 or 1 reads them on the current thread
+    pub threads: usize,
+    /// Whether to list the content of symbolic links to folders and measure the targets
+    /// of links instead of the links themselves
+    pub follow: bool,
+}
+
+/// Entries to leave out of a scanned tree, see [`DirEntry::filter`].
+#[derive(Debug, Clone, Default)]
+pub struct TreeFilter {
+    /// Names of entries to list, with the folders leading to them
+    pub include: Option<Regex>,
+    /// Names of entries to leave out, wins over `include`
+    pub exclude: Option<Regex>,
+    /// Whether to leave out all files
+    pub dirs_only: bool,
+    /// Whether to leave out folders without content, files left out by `dirs_only` still
+    /// count as content
+    pub prune: bool,
+}
+
+/// Regular expression for a glob matching whole names, e.g. `.*\.rs` for `*.rs`.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                // character class, negated by `!` like in shells
+                regex.push('[');
+                let mut class = chars.by_ref().take_while(|c| *c != ']').peekable();
+                if class.next_if(|c| matches!(c, '!' | '^')).is_some() {
+                    regex.push('^');
+                }
+                for c in class {
+                    if matches!(c, '\\' | '[' | '&' | '~') {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+/// #### Build one regular expression matching names by any of several patterns.
+///
+/// ##### Arguments
+/// * `patterns`: Globs matching the whole name or regular expressions matching part of it
+/// * `is_regex`: Whether the patterns are regular expressions
+/// * `return`: `None` without patterns
+fn names_regex(patterns: &[String], is_regex: bool) -> Result<Option<Regex>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let alternatives: Vec<String> = patterns
+        .iter()
+        .map(|pattern| {
+            if is_regex {
+                format!("(?:{pattern})")
+            } else {
+                format!("^(?:{})$", glob_to_regex(pattern))
+            }
+        })
+        .collect();
+    let regex = Regex::new(&alternatives.join("|")).map_err(RuclifsError::InvalidRegex)?;
+    Ok(Some(regex))
+}
+
+impl TreeFilter {
+    /// Whether entries are left out by their names or content, which needs the whole tree.
+    fn selects(&self) -> bool {
+        self.include.is_some() || self.exclude.is_some() || self.prune
+    }
+}
+
+impl Default for ScanOptions {
+    /// Whole tree with names only, read on one thread per CPU.
+    fn default() -> Self {
+        ScanOptions {
+            th_depth: -1,
+            read_size: false,
+            disk_usage: false,
+            count: false,
+            threads: default_jobs(),
+            follow: false,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Whether content below the deepest level listed has to be read.
+    fn measures(&self) -> bool {
+        self.read_size || self.disk_usage || self.count
+    }
+}
+
+/// Keys to sort the entries of a folder by.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    /// Name, character by character
+    Name,
+    /// Name, with numbers compared by value, so file2 comes before file10
+    Natural,
+    /// Size, biggest first, folders by the size of their content
+    Size,
+    /// Modification time, newest first
+    Mtime,
+    /// Extension, then name
+    Ext,
+}
+
+/// Number of files and folders in a folder and all of its subfolders.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub files: u64,
+    pub dirs: u64,
+}
+
+/// Target of a symbolic link.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkTarget {
+    /// Path the link points to, as stored in the link
+    pub target: PathBuf,
+    /// Whether the target does not exist
+    pub broken: bool,
+}
+
+/// File in a scanned tree.
+#[derive(Clone, Debug)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    /// Size in bytes, only read if sizes were requested
+    pub size: Option<u64>,
+    /// Size allocated on disk in bytes, only read for disk usage
+    pub allocated: Option<u64>,
+    /// Time of the last modification, if available
+    pub modified: Option<SystemTime>,
+    /// Device and inode, only available on Unix
+    pub inode: Option<(u64, u64)>,
+    /// Number of hard links, only available on Unix
+    pub num_links: Option<u64>,
+    /// File type and permission bits, only available on Unix
+    pub mode: Option<u32>,
+    /// User and group id of the owner, only available on Unix
+    pub owner: Option<(u32, u32)>,
+    /// Target if the file is a symbolic link, links to folders are files unless followed
+    pub link: Option<LinkTarget>,
 }
 
-#[derive(Clone)]
-struct DirEntry<'a> {
+/// Folder in a scanned tree, see [`scan`].
+///
+/// The tree only holds data, it is printed by a
+/// [`TreeRenderer`](crate::functions::tree_render::TreeRenderer).
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    /// Whether the content of the folder could be read
+    pub have_access: bool,
+    pub children_file: Vec<FileEntry>,
+    pub children_dir: Vec<DirEntry>,
+    /// Size of all content, `None` if not requested or a subfolder could not be read
+    pub size: Option<u64>,
+    /// Size allocated on disk by the folder and its content, only read for disk usage
+    pub allocated: Option<u64>,
+    /// Files and folders below, only counted if requested, content of subfolders that
+    /// could not be read is missing
+    pub counts: Option<Counts>,
+    /// Time of the last modification, if available
+    pub modified: Option<SystemTime>,
+    /// Device and inode, only available on Unix
+    pub inode: Option<(u64, u64)>,
+    /// Number of hard links, only available on Unix
+    pub num_links: Option<u64>,
+    /// File type and permission bits, only available on Unix
+    pub mode: Option<u32>,
+    /// User and group id of the owner, only available on Unix
+    pub owner: Option<(u32, u32)>,
+    /// Whether the content was left out for being below the deepest level listed
+    pub truncated: bool,
+    /// Target if the folder is a followed symbolic link
+    pub link: Option<LinkTarget>,
+    /// Whether the folder is one of its own parents reached by a link, so its content
+    /// was left out
+    pub cycle: bool,
+}
+
+/// Folder to read in the parallel scan, see [`scan`].
+struct ScanJob {
     path: PathBuf,
-    prefix: String,
-    connector: String,
     depth: i32,
-    th_depth: i32,
-    show_size: bool,
-    have_access: bool,
-    children_file: Vec<FileEntry<'a>>,
-    children_dir: Vec<DirEntry<'a>>,
-    size: Option<u64>,
-    styler_size: &'a Styler,
-    styler_folder: &'a Styler,
-}
-
-impl FileEntry<'_> {
-    fn build(
-        path: PathBuf,
-        prefix: String,
-        connector: String,
-        show_size: bool,
-        styler_size: &Styler,
-    ) -> FileEntry {
-        let size: Option<u64>;
-        if show_size {
-            size = Some(metadata(&path).unwrap().len());
-        } else {
-            size = None;
+    /// Device and inode of the parent folders, to detect links back to them
+    ancestors: Vec<(u64, u64)>,
+}
+
+/// Content of a folder read by the parallel scan, see [`scan`].
+enum Listing {
+    /// Folder listed in the tree, files and subfolders in order of their paths
+    Entries {
+        files: Vec<FileEntry>,
+        dirs: Vec<DirEntry>,
+    },
+    /// Folder below the deepest level listed, only measured
+    Measured {
+        /// Size of the files that are not hard linked
+        size: u64,
+        /// Size allocated by those files and the subfolders themselves
+        allocated: u64,
+        /// Hard linked files as inode, size and allocated size, in order of their paths
+        linked: Vec<((u64, u64), u64, u64)>,
+        /// Subfolders in order of their paths
+        dirs: Vec<PathBuf>,
+        /// Number of files, including hard links and symbolic links
+        num_files: u64,
+    },
+    /// Folder that could not be read
+    NoAccess,
+}
+
+/// Sizes of the content of a folder, measured without building entries.
+struct WalkSize {
+    size: u64,
+    allocated: u64,
+    /// Whether all subfolders could be read
+    all_access: bool,
+}
+
+/// Child of a folder, see [`DirEntry::children`].
+#[derive(Clone, Copy, Debug)]
+pub enum Entry<'a> {
+    File(&'a FileEntry),
+    Dir(&'a DirEntry),
+}
+
+/// Last component of a path, the whole path if it has none, e.g. for `/`.
+fn get_name(path: &Path) -> Cow<'_, str> {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+}
+
+/// #### Compare names with digit runs compared by their value.
+///
+/// Names equal apart from leading zeros are compared character by character, e.g.
+/// `file02` < `file2` < `file10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut chars_a = a.chars().peekable();
+    let mut chars_b = b.chars().peekable();
+    loop {
+        match (chars_a.peek(), chars_b.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let mut digits_a = String::new();
+                while let Some(c) = chars_a.next_if(|c| c.is_ascii_digit()) {
+                    digits_a.push(c);
+                }
+                let mut digits_b = String::new();
+                while let Some(c) = chars_b.next_if(|c| c.is_ascii_digit()) {
+                    digits_b.push(c);
+                }
+                let trimmed_a = digits_a.trim_start_matches('0');
+                let trimmed_b = digits_b.trim_start_matches('0');
+                let ordering = trimmed_a
+                    .len()
+                    .cmp(&trimmed_b.len())
+                    .then_with(|| trimmed_a.cmp(trimmed_b));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ordering = ca.cmp(cb);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                chars_a.next();
+                chars_b.next();
+            }
         }
+    }
+}
+
+/// Bytes allocated on disk, `st_blocks` is counted in units of 512 bytes.
+#[cfg(unix)]
+fn get_allocated(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+fn get_allocated(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Device, inode and number of hard links.
+#[cfg(unix)]
+fn get_links(metadata: &Metadata) -> (Option<(u64, u64)>, Option<u64>) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        Some((metadata.dev(), metadata.ino())),
+        Some(metadata.nlink()),
+    )
+}
+
+#[cfg(not(unix))]
+fn get_links(_metadata: &Metadata) -> (Option<(u64, u64)>, Option<u64>) {
+    (None, None)
+}
+
+/// Mode bits and user and group id of the owner.
+#[cfg(unix)]
+fn get_owner(metadata: &Metadata) -> (Option<u32>, Option<(u32, u32)>) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        Some(metadata.mode()),
+        Some((metadata.uid(), metadata.gid())),
+    )
+}
+
+#[cfg(not(unix))]
+fn get_owner(_metadata: &Metadata) -> (Option<u32>, Option<(u32, u32)>) {
+    (None, None)
+}
+
+/// Metadata of the link itself unless following links, of the link if its target is gone.
+fn read_metadata(path: &Path, follow: bool) -> Option<Metadata> {
+    if follow {
+        metadata(path).or_else(|_| symlink_metadata(path)).ok()
+    } else {
+        symlink_metadata(path).ok()
+    }
+}
+
+/// Target of a path if it is a symbolic link.
+fn get_link(path: &Path) -> Option<LinkTarget> {
+    let target = read_link(path).ok()?;
+    Some(LinkTarget {
+        target,
+        broken: metadata(path).is_err(),
+    })
+}
+
+/// Whether folders at `depth` are listed with their content.
+fn is_listed(depth: i32, options: &ScanOptions) -> bool {
+    options.th_depth < 0 || depth < options.th_depth
+}
+
+/// #### Read a folder for the parallel scan.
+///
+/// Folders that are listed get entries built for their content, those below the deepest
+/// level are only measured. Subfolders that are parents of the folder as well are not
+/// read again.
+///
+/// ##### Arguments
+/// * `job`: Folder to read
+/// * `options`: Depth, sizes and links to read
+/// * `return`: Path and content of the folder, subfolders to read next
+fn read_listing(job: ScanJob, options: &ScanOptions) -> ((PathBuf, Listing), Vec<ScanJob>) {
+    let ScanJob {
+        path,
+        depth,
+        mut ancestors,
+    } = job;
+    let mut content: Vec<(bool, PathBuf)> = match fs::read_dir(&path) {
+        Ok(c) => c
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .map(|path| {
+                if options.follow {
+                    (path.is_dir(), path)
+                } else {
+                    (symlink_metadata(&path).is_ok_and(|m| m.is_dir()), path)
+                }
+            })
+            .collect(),
+        Err(_) => return ((path, Listing::NoAccess), Vec::new()),
+    };
+    content.sort();
+    if let Some(inode) = metadata(&path).ok().and_then(|m| get_links(&m).0) {
+        ancestors.push(inode);
+    }
+    let is_cycle = |metadata: &Metadata| {
+        get_links(metadata)
+            .0
+            .is_some_and(|inode| ancestors.contains(&inode))
+    };
+
+    // subfolders below the deepest level are only read to measure them
+    let read_subdirs = is_listed(depth + 1, options) || options.measures();
+    let mut next = Vec::new();
+    let listing = if is_listed(depth, options) {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for (is_dir, path) in content {
+            if !is_dir {
+                files.push(FileEntry::build(path, options));
+                continue;
+            }
+            let mut dir = DirEntry::build(path, options);
+            dir.cycle = metadata(&dir.path).is_ok_and(|m| is_cycle(&m));
+            if read_subdirs && !dir.cycle {
+                next.push(ScanJob {
+                    path: dir.path.clone(),
+                    depth: depth + 1,
+                    ancestors: ancestors.clone(),
+                });
+            }
+            dirs.push(dir);
+        }
+        Listing::Entries { files, dirs }
+    } else {
+        let (mut size, mut allocated) = (0, 0);
+        let mut linked = Vec::new();
+        let mut dirs = Vec::new();
+        let mut num_files = 0;
+        for (is_dir, path) in content {
+            let Some(metadata) = read_metadata(&path, options.follow) else {
+                continue;
+            };
+            let file_allocated = get_allocated(&metadata).unwrap_or_default();
+            if is_dir {
+                // parents reached by a link are counted already
+                if is_cycle(&metadata) {
+                    continue;
+                }
+                allocated += file_allocated;
+                next.push(ScanJob {
+                    path: path.clone(),
+                    depth: depth + 1,
+                    ancestors: ancestors.clone(),
+                });
+                dirs.push(path);
+                continue;
+            }
+            num_files += 1;
+            match get_links(&metadata) {
+                (Some(inode), Some(2..)) if options.disk_usage => {
+                    linked.push((inode, metadata.len(), file_allocated))
+                }
+                _ => {
+                    size += metadata.len();
+                    allocated += file_allocated;
+                }
+            }
+        }
+        Listing::Measured {
+            size,
+            allocated,
+            linked,
+            dirs,
+            num_files,
+        }
+    };
+    ((path, listing), next)
+}
+
+/// #### Sum up the sizes of everything in a measured folder.
+///
+/// Files are counted before subfolders and both in order of their paths, the same order
+/// `DirEntry::get_size` counts them in.
+///
+/// ##### Arguments
+/// * `path`: Folder to measure
+/// * `listings`: Folders read by the scan
+/// * `seen_inodes`: Hard linked files counted already, `None` to count every link
+/// * `return`: Sizes, `None` if the folder itself could not be read
+fn measure(
+    path: &Path,
+    listings: &HashMap<PathBuf, Listing>,
+    mut seen_inodes: Option<&mut HashSet<(u64, u64)>>,
+) -> Option<WalkSize> {
+    let Some(Listing::Measured {
+        size,
+        allocated,
+        linked,
+        dirs,
+        ..
+    }) = listings.get(path)
+    else {
+        return None;
+    };
+    let mut measured = WalkSize {
+        size: *size,
+        allocated: *allocated,
+        all_access: true,
+    };
+    for (inode, size, allocated) in linked {
+        if let Some(seen_inodes) = seen_inodes.as_deref_mut() {
+            if !seen_inodes.insert(*inode) {
+                continue;
+            }
+        }
+        measured.size += size;
+        measured.allocated += allocated;
+    }
+    for dir in dirs {
+        match measure(dir, listings, seen_inodes.as_deref_mut()) {
+            Some(child) => {
+                measured.size += child.size;
+                measured.allocated += child.allocated;
+                measured.all_access &= child.all_access;
+            }
+            None => measured.all_access = false,
+        }
+    }
+    Some(measured)
+}
+
+/// Count the files and folders in a measured folder, unreadable ones count as empty.
+fn measure_counts(path: &Path, listings: &HashMap<PathBuf, Listing>) -> Counts {
+    let Some(Listing::Measured {
+        dirs, num_files, ..
+    }) = listings.get(path)
+    else {
+        return Counts::default();
+    };
+    let mut counts = Counts {
+        files: *num_files,
+        dirs: dirs.len() as u64,
+    };
+    for dir in dirs {
+        let child = measure_counts(dir, listings);
+        counts.files += child.files;
+        counts.dirs += child.dirs;
+    }
+    counts
+}
+
+/// Extension of a name, empty if it has none.
+fn get_extension(name: &str) -> &str {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+}
+
+impl SortKey {
+    /// Ordering of two entries given by name, size and modification time.
+    fn compare(
+        self,
+        (name_a, size_a, modified_a): (&str, Option<u64>, Option<SystemTime>),
+        (name_b, size_b, modified_b): (&str, Option<u64>, Option<SystemTime>),
+    ) -> Ordering {
+        let ordering = match self {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Natural => natural_cmp(name_a, name_b),
+            SortKey::Size => size_b.cmp(&size_a),
+            SortKey::Mtime => modified_b.cmp(&modified_a),
+            SortKey::Ext => get_extension(name_a).cmp(get_extension(name_b)),
+        };
+        ordering.then_with(|| name_a.cmp(name_b))
+    }
+}
+
+impl FileEntry {
+    fn build(path: PathBuf, options: &ScanOptions) -> FileEntry {
+        let metadata = read_metadata(&path, options.follow);
+        let size = match &metadata {
+            Some(m) if options.read_size => Some(m.len()),
+            _ => None,
+        };
+        let allocated = match &metadata {
+            Some(m) if options.disk_usage => get_allocated(m),
+            _ => None,
+        };
+        let (inode, num_links) = metadata.as_ref().map_or((None, None), get_links);
+        let (mode, owner) = metadata.as_ref().map_or((None, None), get_owner);
+        let modified = metadata.and_then(|m| m.modified().ok());
         FileEntry {
-            path: path,
-            prefix: prefix,
-            connector: connector,
-            show_size: show_size,
-            size: size,
-            styler_size: styler_size,
+            link: get_link(&path),
+            path,
+            size,
+            allocated,
+            modified,
+            inode,
+            num_links,
+            mode,
+            owner,
         }
     }
+
+    pub fn name(&self) -> Cow<'_, str> {
+        get_name(&self.path)
+    }
 }
 
-impl DirEntry<'_> {
-    fn build<'a>(
-        path: PathBuf,
-        prefix: String,
-        connector: String,
-        depth: i32,
-        th_depth: i32,
-        show_size: bool,
-        styler_size: &'a Styler,
-        styler_folder: &'a Styler,
-    ) -> DirEntry<'a> {
+impl DirEntry {
+    fn build(path: PathBuf, options: &ScanOptions) -> DirEntry {
+        let metadata = metadata(&path).ok();
+        // the folder itself takes blocks as well, like counted by du
+        let allocated = match &metadata {
+            Some(m) if options.disk_usage => get_allocated(m),
+            _ => None,
+        };
+        let (inode, num_links) = metadata.as_ref().map_or((None, None), get_links);
+        let (mode, owner) = metadata.as_ref().map_or((None, None), get_owner);
+        let modified = metadata.and_then(|m| m.modified().ok());
         DirEntry {
-            path: path,
-            prefix: prefix,
-            connector: connector,
-            depth: depth,
-            th_depth: th_depth,
-            show_size: show_size,
-            have_access: true, //TODO: have logic here
+            link: get_link(&path),
+            path,
+            have_access: true,
             children_file: Vec::new(),
             children_dir: Vec::new(),
             size: None,
-            styler_size: styler_size,
-            styler_folder: styler_folder,
+            allocated,
+            counts: None,
+            modified,
+            inode,
+            num_links,
+            mode,
+            owner,
+            truncated: false,
+            cycle: false,
         }
     }
-    fn get_children(&mut self) {
-        let mut children_file: Vec<FileEntry> = Vec::new();
-        let mut children_dir: Vec<DirEntry> = Vec::new();
 
-        if (self.th_depth >= 0) && (self.depth >= self.th_depth) && !self.show_size {
-            self.children_file = children_file;
-            self.children_dir = children_dir;
-            return;
-        }
-
-        let mut child_prefix = self.prefix.clone();
-        if self.connector == TEE {
-            child_prefix.push_str(PIPE_PREFIX);
-        } else if self.connector == ELBOW {
-            child_prefix.push_str(SPACE_PREFIX);
-        }
-
-        let mut content: Vec<PathBuf>;
+    pub fn name(&self) -> Cow<'_, str> {
+        get_name(&self.path)
+    }
 
-        match fs::read_dir(self.path.clone()) {
-            Ok(c) => {
-                content = c
-                    .filter_map(|e| e.ok())
-                    .map(|e| e.path())
-                    .collect::<Vec<_>>();
-                content.sort_by_key(|e| (e.is_dir(), e.to_owned()))
-            }
-            Err(_) => {
-                self.have_access = false;
-                content = Vec::new()
-            }
+    /// Files and folders in the order they are listed, folders last unless `dirs_first`.
+    pub fn children(&self, dirs_first: bool) -> impl Iterator<Item = Entry<'_>> {
+        let files = self.children_file.iter().map(Entry::File);
+        let dirs = self.children_dir.iter().map(Entry::Dir);
+        let (first, last): (Vec<Entry>, Vec<Entry>) = if dirs_first {
+            (dirs.collect(), files.collect())
+        } else {
+            (files.collect(), dirs.collect())
         };
+        first.into_iter().chain(last)
+    }
 
-        let len_content = content.len();
-
-        for (num_path, path) in content.iter().enumerate() {
-            let child_connector: String;
-            if num_path + 1 < len_content {
-                child_connector = TEE.to_string()
+    /// #### Sort files and folders of the whole tree.
+    ///
+    /// Sorting by size uses the aggregated sizes, so sizes should have been read.
+    ///
+    /// ##### Arguments
+    /// * `key`: What to sort by, ties are sorted by name
+    /// * `reverse`: Whether to reverse the order
+    pub fn sort(&mut self, key: SortKey, reverse: bool) {
+        let directed = |ordering: Ordering| {
+            if reverse {
+                ordering.reverse()
             } else {
-                child_connector = ELBOW.to_string()
-            }
-            if path.is_dir() {
-                let mut new_dir_entry = DirEntry::build(
-                    path.to_path_buf(),
-                    child_prefix.clone(),
-                    child_connector,
-                    self.depth + 1,
-                    self.th_depth,
-                    self.show_size,
-                    self.styler_size,
-                    self.styler_folder,
-                );
-                new_dir_entry.get_children();
-                children_dir.push(new_dir_entry);
-            } else {
-                children_file.push(FileEntry::build(
-                    path.to_path_buf(),
-                    child_prefix.clone(),
-                    child_connector,
-                    self.show_size,
-                    self.styler_size,
-                ));
+                ordering
             }
+        };
+        self.children_file.sort_by(|a, b| {
+            directed(key.compare(
+                (&a.name(), a.size, a.modified),
+                (&b.name(), b.size, b.modified),
+            ))
+        });
+        self.children_dir.sort_by(|a, b| {
+            directed(key.compare(
+                (&a.name(), a.size, a.modified),
+                (&b.name(), b.size, b.modified),
+            ))
+        });
+        for child in self.children_dir.iter_mut() {
+            child.sort(key, reverse);
         }
-        self.children_file = children_file;
-        self.children_dir = children_dir;
     }
 
-    fn get_size(&mut self) {
-        if !self.have_access {
-            self.size = None;
+    /// #### Put the folders read by the scan together into a tree.
+    ///
+    /// ##### Arguments
+    /// * `depth`: Depth of this folder in the tree
+    /// * `listings`: Folders read by the scan, listed ones are taken out
+    /// * `options`: Depth and sizes to read
+    fn get_children(
+        &mut self,
+        depth: i32,
+        listings: &mut HashMap<PathBuf, Listing>,
+        options: &ScanOptions,
+    ) {
+        if self.cycle {
             return;
         }
-        let mut all_access = true;
-        let mut size: u64 = 0;
-        for i in self.children_dir.iter_mut() {
-            i.get_size();
-            if i.size == None {
-                all_access = false;
+        if !is_listed(depth, options) {
+            self.truncated = true;
+            return;
+        }
+        match listings.remove(&self.path) {
+            Some(Listing::Entries { files, dirs }) => {
+                self.children_file = files;
+                self.children_dir = dirs;
             }
-            size += i.size.unwrap_or_default();
+            _ => self.have_access = false,
         }
-        for i in self.children_file.iter() {
-            size += i.size.unwrap_or_default();
+        for child in self.children_dir.iter_mut() {
+            child.get_children(depth + 1, listings, options);
         }
-        self.size = if all_access { Some(size) } else { None };
     }
-}
 
-impl fmt::Display for FileEntry<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = format!(
-            "{}{}{}",
-            self.prefix,
-            self.connector,
-            self.path.file_name().unwrap().to_str().unwrap(),
-        );
+    /// #### Leave out entries of the whole tree.
+    ///
+    /// Sizes were read before and still cover everything, counts are updated to the
+    /// entries left.
+    ///
+    /// ##### Arguments
+    /// * `filter`: Entries to leave out
+    pub fn filter(&mut self, filter: &TreeFilter) {
+        self.apply_filter(filter);
+    }
 
-        if self.show_size {
-            result.push_str(
-                format!(" {:6}", bites2str(self.size.unwrap(), self.styler_size,)).as_str(),
-            );
+    /// Filter the tree, returns whether the folder has content, hidden files included.
+    fn apply_filter(&mut self, filter: &TreeFilter) -> bool {
+        if let Some(exclude) = &filter.exclude {
+            self.children_file
+                .retain(|file| !exclude.is_match(&file.name()));
+            self.children_dir
+                .retain(|dir| !exclude.is_match(&dir.name()));
         }
-
-        write!(f, "{}\n", result)
+        if let Some(include) = &filter.include {
+            self.children_file
+                .retain(|file| include.is_match(&file.name()));
+        }
+        let mut has_content = !self.children_file.is_empty();
+        self.children_dir.retain_mut(|child| {
+            let keep = match &filter.include {
+                // matching folders keep all their content
+                Some(include) if include.is_match(&child.name()) => {
+                    let filter = TreeFilter {
+                        include: None,
+                        ..filter.clone()
+                    };
+                    child.apply_filter(&filter);
+                    true
+                }
+                // other folders only lead to matches
+                Some(_) => child.apply_filter(filter),
+                // folders with unknown content are not empty
+                None => {
+                    child.apply_filter(filter)
+                        || child.truncated
+                        || child.cycle
+                        || !child.have_access
+                        || !filter.prune
+                }
+            };
+            has_content |= keep;
+            keep
+        });
+        if filter.dirs_only {
+            self.children_file.clear();
+        }
+        // folders below the deepest level keep the counts measured for their content
+        if self.counts.is_some() && !self.truncated && !self.cycle {
+            let mut counts = Counts {
+                files: self.children_file.len() as u64,
+                dirs: self.children_dir.len() as u64,
+            };
+            for child in &self.children_dir {
+                let child_counts = child.counts.unwrap_or_default();
+                counts.files += child_counts.files;
+                counts.dirs += child_counts.dirs;
+            }
+            self.counts = Some(counts);
+        }
+        // counts measured below the deepest level include files
+        if let Some(counts) = self.counts.as_mut().filter(|_| filter.dirs_only) {
+            counts.files = 0;
+        }
+        has_content
     }
-}
 
-impl fmt::Display for DirEntry<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = format!(
-            "{}{}{}",
-            self.prefix,
-            self.connector,
-            self.styler_folder
-                .style(self.path.file_name().unwrap().to_str().unwrap()),
-        );
+    /// #### Count the files and folders rendered below the folder.
+    ///
+    /// ##### Arguments
+    /// * `depth`: Level of the folder, 0 for the root
+    /// * `options`: Deepest level rendered
+    pub fn shown_counts(&self, depth: i32, options: &RenderOptions) -> Counts {
+        let mut counts = Counts::default();
+        if !options.shows_children(depth) {
+            return counts;
+        }
+        counts.files = self.children_file.len() as u64;
+        counts.dirs = self.children_dir.len() as u64;
+        for child in &self.children_dir {
+            let child_counts = child.shown_counts(depth + 1, options);
+            counts.files += child_counts.files;
+            counts.dirs += child_counts.dirs;
+        }
+        counts
+    }
 
-        if self.show_size {
-            let size_suffix: String;
+    /// #### Count files and folders of the content.
+    ///
+    /// ##### Arguments
+    /// * `listings`: Folders below the deepest level read by the scan
+    fn get_counts(&mut self, listings: &HashMap<PathBuf, Listing>) {
+        let counts = if self.cycle {
+            // the content is counted with the parent the link points to
+            Counts::default()
+        } else if self.truncated {
+            measure_counts(&self.path, listings)
+        } else {
+            let mut counts = Counts {
+                files: self.children_file.len() as u64,
+                dirs: self.children_dir.len() as u64,
+            };
+            for child in self.children_dir.iter_mut() {
+                child.get_counts(listings);
+                let child_counts = child.counts.unwrap_or_default();
+                counts.files += child_counts.files;
+                counts.dirs += child_counts.dirs;
+            }
+            counts
+        };
+        self.counts = Some(counts);
+    }
 
-            if self.have_access {
-                size_suffix = match self.size {
-                    Some(c) => format!(" {:6}", bites2str(c, self.styler_size)),
-                    None => self.styler_size.style(" size unknown"),
+    /// #### Sum up sizes of the content.
+    ///
+    /// ##### Arguments
+    /// * `listings`: Folders below the deepest level read by the scan
+    /// * `seen_inodes`: Hard linked files counted already, further links to them are
+    ///   not counted again, `None` to count every link
+    fn get_size(
+        &mut self,
+        listings: &HashMap<PathBuf, Listing>,
+        mut seen_inodes: Option<&mut HashSet<(u64, u64)>>,
+    ) {
+        if self.cycle {
+            // the content is counted with the parent the link points to
+            self.size = Some(0);
+            self.allocated = self.allocated.map(|_| 0);
+            return;
+        }
+        if self.truncated {
+            // no entries below the deepest level, just measure the content
+            match measure(&self.path, listings, seen_inodes) {
+                Some(measured) if measured.all_access => {
+                    self.size = Some(measured.size);
+                    self.allocated = self.allocated.map(|a| a + measured.allocated);
                 }
-            } else {
-                size_suffix = String::from("  \u{1b}[31maccess error\u{1b}[0m")
-            };
-            result.push_str(size_suffix.as_str());
+                Some(_) => {
+                    self.size = None;
+                    self.allocated = None;
+                }
+                None => {
+                    self.have_access = false;
+                    self.size = None;
+                    self.allocated = None;
+                }
+            }
+            return;
         }
-        write!(f, "{}\n", result)?;
-
-        // print children if depth level permits
-        if (self.th_depth <= 0) || (self.depth < self.th_depth) {
-            for child in self.children_file.clone().iter() {
-                write!(f, "{}", child)?;
+        if !self.have_access {
+            self.size = None;
+            self.allocated = None;
+            return;
+        }
+        let mut all_access = true;
+        let mut size: u64 = 0;
+        let mut allocated = self.allocated;
+        // files first, so hard links are counted in the folder closest to the root
+        for i in self.children_file.iter() {
+            if let (Some(seen_inodes), Some(inode)) = (seen_inodes.as_deref_mut(), i.inode) {
+                if i.num_links.unwrap_or(1) > 1 && !seen_inodes.insert(inode) {
+                    continue;
+                }
             }
-            for child in self.children_dir.clone().iter() {
-                write!(f, "{}", child)?;
+            size += i.size.unwrap_or_default();
+            allocated = allocated.map(|a| a + i.allocated.unwrap_or_default());
+        }
+        for i in self.children_dir.iter_mut() {
+            i.get_size(listings, seen_inodes.as_deref_mut());
+            if i.size.is_none() {
+                all_access = false;
             }
+            size += i.size.unwrap_or_default();
+            allocated = allocated.map(|a| a + i.allocated.unwrap_or_default());
         }
-        Ok(())
+        self.size = if all_access { Some(size) } else { None };
+        self.allocated = if all_access { allocated } else { None };
     }
 }
 
-pub fn build_tree(path: &PathBuf, th_depth: i32, show_size: bool) {
-    //let root_path: &PathBuf;
+/// #### Scan a folder into a tree.
+///
+/// Folders are read on `options.threads` threads, the tree is put together in order of
+/// the paths afterwards, so it does not depend on the number of threads.
+///
+/// ##### Arguments
+/// * `path`: Root folder of the tree
+/// * `options`: Depth and sizes to read
+/// * `return`: Root of the tree
+pub fn scan(path: &Path, options: &ScanOptions) -> Result<DirEntry> {
     // for some reason powershell does not expand this
-    let root_path = match path.to_str().unwrap() {
-        "." => current_dir().expect("Could not identify current path"),
-        _ => path.to_owned(),
+    let root_path = if path.as_os_str() == "." {
+        current_dir().map_err(RuclifsError::io(path))?
+    } else {
+        path.to_owned()
     };
+    let mut root_dir = DirEntry::build(root_path, options);
+    let roots = if is_listed(0, options) || options.measures() {
+        vec![ScanJob {
+            path: root_dir.path.clone(),
+            depth: 0,
+            ancestors: Vec::new(),
+        }]
+    } else {
+        Vec::new()
+    };
+    let mut listings: HashMap<PathBuf, Listing> =
+        walk_parallel(roots, options.threads, |job| read_listing(job, options))
+            .into_iter()
+            .collect();
+    root_dir.get_children(0, &mut listings, options);
+    if options.read_size || options.disk_usage {
+        let mut seen_inodes = HashSet::new();
+        root_dir.get_size(&listings, options.disk_usage.then_some(&mut seen_inodes));
+    }
+    if options.count {
+        root_dir.get_counts(&listings);
+    }
+    Ok(root_dir)
+}
 
-    let styler_size = Styler::build("cyan", "", false, false, "").unwrap();
-    let styler_folder = Styler::build("yellow", "", false, false, "").unwrap();
-
-    let mut root_dir = DirEntry::build(
-        root_path.to_owned(),
-        String::from(""),
-        String::from(""),
-        0,
-        th_depth,
-        show_size,
-        &styler_size,
-        &styler_folder,
-    );
-    root_dir.get_children();
-    if show_size {
-        root_dir.get_size();
+pub fn build_tree(
+    path: &Path,
+    scan_options: &ScanOptions,
+    filter: &TreeFilter,
+    render_options: &RenderOptions,
+    format: TreeFormat,
+    sort: SortKey,
+    reverse: bool,
+) -> Result<()> {
+    // filters look at the whole tree, so folders leading to matches below the deepest
+    // level are kept and counts only cover the entries left
+    let scan_options = if filter.selects() {
+        ScanOptions {
+            th_depth: -1,
+            ..scan_options.clone()
+        }
+    } else {
+        scan_options.clone()
+    };
+    let mut root_dir = scan(path, &scan_options)?;
+    root_dir.filter(filter);
+    root_dir.sort(sort, reverse);
+    let out = format.renderer().render(&root_dir, render_options);
+    if format.is_machine_readable() {
+        print!("{out}");
+    } else {
+        println!("{out}");
+        // like the entries, the summary stops at the deepest level rendered
+        if scan_options.count {
+            let counts = root_dir.shown_counts(0, render_options);
+            println!("{}", counts_text(counts, render_options.dirs_only));
+        }
     }
-    println!("{}", root_dir)
+    Ok(())
 }
 
 #[cfg(test)]
 mod test_tree {
-    use crate::utils::cli::Styler;
-    use std::fs::{create_dir, File};
+    use crate::functions::tree_render::{AsciiRenderer, RenderOptions, TreeRenderer};
+    use std::fs::{self, create_dir, File};
     use std::path::{Path, PathBuf};
     use tempfile::tempdir;
 
-    use super::DirEntry;
+    use super::{
+        glob_to_regex, names_regex, natural_cmp, scan, Counts, DirEntry, Entry, ScanOptions,
+        SortKey, TreeFilter,
+    };
+    use crate::functions::tree_render::counts_text;
+    use std::cmp::Ordering;
+
+    fn render_plain(dir: &DirEntry, show_size: bool) -> String {
+        let options = RenderOptions {
+            show_size,
+            ..Default::default()
+        };
+        AsciiRenderer::plain().render(dir, &options)
+    }
+
+    fn get_scan_options(read_size: bool) -> ScanOptions {
+        ScanOptions {
+            read_size,
+            threads: 4,
+            ..Default::default()
+        }
+    }
 
     fn set_up_dir(dir: &Path) -> PathBuf {
         let rootdir = dir.join("root_dir");
@@ -309,34 +1164,20 @@ mod test_tree {
         File::create(some_subsubdir.join("some_subsubdir_file_2.rs")).unwrap();
         File::create(some_subsubdir.join("some_subsubdir_file_3.rs")).unwrap();
 
-        return rootdir;
+        rootdir
     }
 
     #[test]
     fn tree_full_depth_no_size() {
         // set up directory
         let tempdir = tempdir().unwrap();
-        let rootdir = set_up_dir(&tempdir.path());
+        let rootdir = set_up_dir(tempdir.path());
 
-        let styler_size = Styler::build("", "", false, false, "").unwrap();
-        let styler_folder = Styler::build("", "", false, false, "").unwrap();
         // build tree
-        let mut root_dir_entry = DirEntry::build(
-            rootdir.to_owned(),
-            String::from(""),
-            String::from(""),
-            0,
-            -1,
-            false,
-            &styler_size,
-            &styler_folder,
-        );
-        root_dir_entry.get_children();
-
-        println!("{}", root_dir_entry);
+        let root_dir_entry = scan(&rootdir, &get_scan_options(false)).unwrap();
 
         assert_eq!(
-            root_dir_entry.to_string(),
+            render_plain(&root_dir_entry, false),
             "\
 root_dir
 ├── some_file_1.txt
@@ -360,28 +1201,13 @@ root_dir
     fn tree_full_depth() {
         // set up directory
         let tempdir = tempdir().unwrap();
-        let rootdir = set_up_dir(&tempdir.path());
+        let rootdir = set_up_dir(tempdir.path());
 
-        let styler_size = Styler::build("", "", false, false, "").unwrap();
-        let styler_folder = Styler::build("", "", false, false, "").unwrap();
         // build tree
-        let mut root_dir_entry = DirEntry::build(
-            rootdir.to_owned(),
-            String::from(""),
-            String::from(""),
-            0,
-            -1,
-            true,
-            &styler_size,
-            &styler_folder,
-        );
-        root_dir_entry.get_children();
-        root_dir_entry.get_size();
-
-        println!("{}", root_dir_entry);
+        let root_dir_entry = scan(&rootdir, &get_scan_options(true)).unwrap();
 
         assert_eq!(
-            root_dir_entry.to_string(),
+            render_plain(&root_dir_entry, true),
             "\
 root_dir    0.00 B
 ├── some_file_1.txt    0.00 B
@@ -400,4 +1226,539 @@ root_dir    0.00 B
         // teardown
         tempdir.close().unwrap();
     }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("file02", "file2"), Ordering::Less);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("b1", "a2"), Ordering::Greater);
+    }
+
+    #[test]
+    fn tree_sorted() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = tempdir.path().join("root_dir");
+        create_dir(&rootdir).unwrap();
+        fs::write(rootdir.join("file10.rs"), "1").unwrap();
+        fs::write(rootdir.join("file2.txt"), "12").unwrap();
+        fs::write(rootdir.join("file1.md"), "123").unwrap();
+        create_dir(rootdir.join("small")).unwrap();
+        create_dir(rootdir.join("big")).unwrap();
+        fs::write(rootdir.join("big").join("content"), "1234").unwrap();
+
+        let mut root_dir_entry = scan(&rootdir, &get_scan_options(true)).unwrap();
+        let get_names = |dir: &DirEntry, dirs_first: bool| {
+            dir.children(dirs_first)
+                .map(|child| match child {
+                    Entry::File(file) => file.name().to_string(),
+                    Entry::Dir(dir) => dir.name().to_string(),
+                })
+                .collect::<Vec<String>>()
+        };
+
+        root_dir_entry.sort(SortKey::Natural, false);
+        assert_eq!(
+            get_names(&root_dir_entry, false),
+            vec!["file1.md", "file2.txt", "file10.rs", "big", "small"]
+        );
+        root_dir_entry.sort(SortKey::Size, false);
+        assert_eq!(
+            get_names(&root_dir_entry, true),
+            vec!["big", "small", "file1.md", "file2.txt", "file10.rs"]
+        );
+        root_dir_entry.sort(SortKey::Ext, true);
+        assert_eq!(
+            get_names(&root_dir_entry, false),
+            vec!["file2.txt", "file10.rs", "file1.md", "small", "big"]
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tree_disk_usage_hard_links() {
+        use std::os::unix::fs::MetadataExt;
+
+        let tempdir = tempdir().unwrap();
+        let rootdir = tempdir.path().join("root_dir");
+        create_dir(&rootdir).unwrap();
+        fs::write(rootdir.join("data"), vec![1u8; 10000]).unwrap();
+        create_dir(rootdir.join("links")).unwrap();
+        fs::hard_link(rootdir.join("data"), rootdir.join("links").join("data")).unwrap();
+
+        let options = ScanOptions {
+            read_size: true,
+            threads: 4,
+            ..Default::default()
+        };
+        assert_eq!(scan(&rootdir, &options).unwrap().size, Some(20000));
+
+        let options = ScanOptions {
+            disk_usage: true,
+            ..options
+        };
+        let root_dir_entry = scan(&rootdir, &options).unwrap();
+        assert_eq!(root_dir_entry.size, Some(10000));
+        let file = &root_dir_entry.children_file[0];
+        assert_eq!(file.num_links, Some(2));
+        assert!(file.allocated.unwrap() >= 10000);
+
+        // blocks of the file once plus those of both folders
+        let get_blocks = |path: &Path| fs::metadata(path).unwrap().blocks() * 512;
+        let allocated_links = get_blocks(&rootdir.join("links"));
+        assert_eq!(
+            root_dir_entry.children_dir[0].allocated,
+            Some(allocated_links)
+        );
+        assert_eq!(
+            root_dir_entry.allocated,
+            Some(get_blocks(&rootdir) + allocated_links + file.allocated.unwrap())
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn tree_depth_limited() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        fs::write(
+            rootdir.join("some_subdir").join("some_subdir_file_1.txt"),
+            "123",
+        )
+        .unwrap();
+        fs::write(rootdir.join("some_file_1.txt"), "1").unwrap();
+
+        let options = ScanOptions {
+            th_depth: 1,
+            read_size: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let root_dir_entry = scan(&rootdir, &options).unwrap();
+        // sizes below the deepest level are measured without building entries
+        assert_eq!(root_dir_entry.size, Some(4));
+        let some_subdir = &root_dir_entry.children_dir[1];
+        assert!(some_subdir.truncated);
+        assert!(some_subdir.children_file.is_empty());
+        assert_eq!(some_subdir.size, Some(3));
+
+        let options = RenderOptions {
+            th_depth: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            AsciiRenderer::plain().render(&root_dir_entry, &options),
+            "\
+root_dir
+├── some_file_1.txt
+├── some_file_2.txt
+├── some_other_subdir
+├── some_subdir
+└── some_subsubdir
+"
+        );
+
+        // depth 0 is the folder only, with and without sizes
+        for read_size in [false, true] {
+            let options = ScanOptions {
+                th_depth: 0,
+                read_size,
+                threads: 4,
+                ..Default::default()
+            };
+            let root_dir_entry = scan(&rootdir, &options).unwrap();
+            assert!(root_dir_entry.children_dir.is_empty());
+            let options = RenderOptions {
+                th_depth: 0,
+                ..Default::default()
+            };
+            assert_eq!(
+                AsciiRenderer::plain().render(&root_dir_entry, &options),
+                "root_dir\n"
+            );
+        }
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn tree_same_for_all_threads() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        for i in 0..20 {
+            let subdir = rootdir.join(format!("dir_{i}")).join("nested");
+            fs::create_dir_all(&subdir).unwrap();
+            fs::write(subdir.join("file"), "1".repeat(i)).unwrap();
+        }
+
+        for th_depth in [-1, 1] {
+            let scan_with = |threads: usize| {
+                let options = ScanOptions {
+                    th_depth,
+                    read_size: true,
+                    disk_usage: true,
+                    threads,
+                    ..Default::default()
+                };
+                let root_dir_entry = scan(&rootdir, &options).unwrap();
+                let options = RenderOptions {
+                    th_depth,
+                    show_size: true,
+                    disk_usage: true,
+                    ..Default::default()
+                };
+                AsciiRenderer::plain().render(&root_dir_entry, &options)
+            };
+            let sequential = scan_with(1);
+            for threads in [2, 8] {
+                assert_eq!(scan_with(threads), sequential);
+            }
+        }
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tree_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tempdir = tempdir().unwrap();
+        let rootdir = tempdir.path().join("root_dir");
+        let subdir = rootdir.join("sub");
+        fs::create_dir_all(&subdir).unwrap();
+        fs::write(rootdir.join("a.txt"), "123").unwrap();
+        symlink("a.txt", rootdir.join("to_a")).unwrap();
+        symlink("missing", rootdir.join("broken")).unwrap();
+        symlink("sub", rootdir.join("to_sub")).unwrap();
+        symlink("..", subdir.join("up")).unwrap();
+
+        let scan_with = |th_depth: i32, follow: bool| {
+            let options = ScanOptions {
+                th_depth,
+                read_size: true,
+                threads: 4,
+                follow,
+                ..Default::default()
+            };
+            scan(&rootdir, &options).unwrap()
+        };
+
+        // links are listed as files with their targets
+        let root_dir_entry = scan_with(-1, false);
+        assert_eq!(
+            render_plain(&root_dir_entry, false),
+            "\
+root_dir
+├── a.txt
+├── broken -> missing
+├── to_a -> a.txt
+├── to_sub -> sub
+└── sub
+    └── up -> ..
+"
+        );
+        // links count with the length of their target path
+        assert_eq!(root_dir_entry.size, Some(3 + 7 + 5 + 3 + 2));
+
+        // followed links to parents are cut
+        let root_dir_entry = scan_with(-1, true);
+        assert_eq!(
+            render_plain(&root_dir_entry, false),
+            "\
+root_dir
+├── a.txt
+├── broken -> missing
+├── to_a -> a.txt
+├── sub
+│   └── up -> ..  recursive, not followed
+└── to_sub -> sub
+    └── up -> ..  recursive, not followed
+"
+        );
+        // the file twice and the broken link itself, cut links count nothing
+        assert_eq!(root_dir_entry.size, Some(3 + 7 + 3));
+        assert!(
+            root_dir_entry.children_file[1]
+                .link
+                .as_ref()
+                .unwrap()
+                .broken
+        );
+        assert_eq!(scan_with(0, true).size, Some(3 + 7 + 3));
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn tree_non_utf8_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tempdir = tempdir().unwrap();
+        let rootdir = tempdir.path().join("root_dir");
+        fs::create_dir_all(rootdir.join(OsStr::from_bytes(b"sub_\xFF"))).unwrap();
+        fs::write(rootdir.join(OsStr::from_bytes(b"a_\xFE.txt")), "").unwrap();
+
+        let options = ScanOptions {
+            threads: 1,
+            ..Default::default()
+        };
+        let root_dir_entry = scan(&rootdir, &options).unwrap();
+        assert_eq!(
+            render_plain(&root_dir_entry, false),
+            "\
+root_dir
+├── a_\u{FFFD}.txt
+└── sub_\u{FFFD}
+"
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn tree_counts() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        create_dir(rootdir.join("some_subdir").join("nested")).unwrap();
+
+        for th_depth in [-1, 1, 0] {
+            let options = ScanOptions {
+                th_depth,
+                count: true,
+                threads: 4,
+                ..Default::default()
+            };
+            let root_dir_entry = scan(&rootdir, &options).unwrap();
+            assert_eq!(root_dir_entry.counts, Some(Counts { files: 7, dirs: 4 }));
+            assert_eq!(
+                counts_text(root_dir_entry.counts.unwrap(), false),
+                "4 directories, 7 files"
+            );
+        }
+
+        let options = ScanOptions {
+            th_depth: 1,
+            count: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let root_dir_entry = scan(&rootdir, &options).unwrap();
+        let options = RenderOptions {
+            th_depth: 1,
+            show_count: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            AsciiRenderer::plain().render(&root_dir_entry, &options),
+            "\
+root_dir [4 directories, 7 files]
+├── some_file_1.txt
+├── some_file_2.txt
+├── some_other_subdir [0 directories, 0 files]
+├── some_subdir [1 directory, 2 files]
+└── some_subsubdir [0 directories, 3 files]
+"
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn tree_counts_filtered() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        create_dir(rootdir.join("some_subdir").join("nested")).unwrap();
+
+        let options = ScanOptions {
+            count: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let mut root_dir_entry = scan(&rootdir, &options).unwrap();
+        root_dir_entry.filter(&TreeFilter {
+            include: names_regex(&[String::from("*.rs")], false).unwrap(),
+            ..TreeFilter::default()
+        });
+        assert_eq!(root_dir_entry.counts, Some(Counts { files: 3, dirs: 2 }));
+
+        let options = RenderOptions {
+            th_depth: 1,
+            show_count: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            AsciiRenderer::plain().render(&root_dir_entry, &options),
+            "\
+root_dir [2 directories, 3 files]
+├── some_subdir [0 directories, 1 file]
+└── some_subsubdir [0 directories, 2 files]
+"
+        );
+        // the summary only counts what is rendered
+        assert_eq!(
+            root_dir_entry.shown_counts(0, &options),
+            Counts { files: 0, dirs: 2 }
+        );
+        assert_eq!(
+            root_dir_entry.shown_counts(0, &RenderOptions::default()),
+            Counts { files: 3, dirs: 2 }
+        );
+
+        // only folders are counted without files
+        let options = ScanOptions {
+            th_depth: 1,
+            count: true,
+            threads: 4,
+            ..Default::default()
+        };
+        let mut root_dir_entry = scan(&rootdir, &options).unwrap();
+        root_dir_entry.filter(&TreeFilter {
+            dirs_only: true,
+            ..TreeFilter::default()
+        });
+        let options = RenderOptions {
+            th_depth: 1,
+            show_count: true,
+            dirs_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            AsciiRenderer::plain().render(&root_dir_entry, &options),
+            "\
+root_dir [4 directories]
+├── some_other_subdir [0 directories]
+├── some_subdir [1 directory]
+└── some_subsubdir [0 directories]
+"
+        );
+        assert_eq!(root_dir_entry.counts, Some(Counts { files: 0, dirs: 4 }));
+        assert_eq!(
+            counts_text(root_dir_entry.shown_counts(0, &options), true),
+            "3 directories"
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn tree_filtered() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        create_dir(rootdir.join("some_subdir").join("empty")).unwrap();
+        let scanned = scan(&rootdir, &get_scan_options(true)).unwrap();
+
+        let render_filtered = |dirs_only: bool, prune: bool| {
+            let mut root_dir_entry = scanned.clone();
+            root_dir_entry.filter(&TreeFilter {
+                dirs_only,
+                prune,
+                ..TreeFilter::default()
+            });
+            render_plain(&root_dir_entry, false)
+        };
+        assert_eq!(
+            render_filtered(true, false),
+            "\
+root_dir
+├── some_other_subdir
+├── some_subdir
+│   └── empty
+└── some_subsubdir
+"
+        );
+        assert_eq!(
+            render_filtered(false, true),
+            "\
+root_dir
+├── some_file_1.txt
+├── some_file_2.txt
+├── some_subdir
+│   ├── some_subdir_file_1.txt
+│   └── some_subdir_file_2.rs
+└── some_subsubdir
+    ├── some_subsubdir_file_1.txt
+    ├── some_subsubdir_file_2.rs
+    └── some_subsubdir_file_3.rs
+"
+        );
+        // folders holding files only are kept
+        assert_eq!(
+            render_filtered(true, true),
+            "root_dir\n├── some_subdir\n└── some_subsubdir\n"
+        );
+
+        tempdir.close().unwrap();
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert_eq!(glob_to_regex("*.rs"), ".*\\.rs");
+        assert_eq!(glob_to_regex("file?[!0-9]"), "file.[^0-9]");
+        let regex = names_regex(&[String::from("*.rs"), String::from("some_s*")], false)
+            .unwrap()
+            .unwrap();
+        assert!(regex.is_match("main.rs"));
+        assert!(regex.is_match("some_subdir"));
+        assert!(!regex.is_match("main.rs.bak"));
+        assert!(names_regex(&[String::from("(")], true).is_err());
+        assert!(names_regex(&[], true).unwrap().is_none());
+    }
+
+    #[test]
+    fn tree_patterns() {
+        let tempdir = tempdir().unwrap();
+        let rootdir = set_up_dir(tempdir.path());
+        let scanned = scan(&rootdir, &get_scan_options(false)).unwrap();
+        let render_filtered = |include: &[&str], exclude: &[&str], is_regex: bool| {
+            let to_strings = |patterns: &[&str]| {
+                patterns
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+            };
+            let mut root_dir_entry = scanned.clone();
+            root_dir_entry.filter(&TreeFilter {
+                include: names_regex(&to_strings(include), is_regex).unwrap(),
+                exclude: names_regex(&to_strings(exclude), is_regex).unwrap(),
+                ..TreeFilter::default()
+            });
+            render_plain(&root_dir_entry, false)
+        };
+
+        // folders leading to matches are kept
+        assert_eq!(
+            render_filtered(&["*.rs"], &[], false),
+            "\
+root_dir
+├── some_subdir
+│   └── some_subdir_file_2.rs
+└── some_subsubdir
+    ├── some_subsubdir_file_2.rs
+    └── some_subsubdir_file_3.rs
+"
+        );
+        // matching folders keep their content, exclusion wins
+        assert_eq!(
+            render_filtered(&["some_subdir", "some_file_1.txt"], &["*_2.*"], false),
+            "\
+root_dir
+├── some_file_1.txt
+└── some_subdir
+    └── some_subdir_file_1.txt
+"
+        );
+        assert_eq!(
+            render_filtered(&[], &["^some_sub"], true),
+            "root_dir\n├── some_file_1.txt\n├── some_file_2.txt\n└── some_other_subdir\n"
+        );
+
+        tempdir.close().unwrap();
+    }
 }
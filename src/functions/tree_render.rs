@@ -0,0 +1,1252 @@
+use crate::functions::tree::{Counts, DirEntry, Entry, FileEntry, LinkTarget};
+use crate::utils::cli::{bites2str, time2str, Styler};
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const ELBOW: &str = "└── ";
+const TEE: &str = "├── ";
+const PIPE_PREFIX: &str = "│   ";
+const SPACE_PREFIX: &str = "    ";
+/// Marker for folders that are links back to one of their parents.
+const CYCLE_NOTE: &str = "recursive, not followed";
+
+/// What to include when rendering a scanned tree.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Deepest level rendered, negative for no limit
+    pub th_depth: i32,
+    /// Whether to render sizes
+    pub show_size: bool,
+    /// Whether to render sizes allocated on disk
+    pub disk_usage: bool,
+    /// Whether to render the number of files and folders below folders
+    pub show_count: bool,
+    /// Whether to list folders before files instead of after them
+    pub dirs_first: bool,
+    /// Whether files were left out, so only folders are counted
+    pub dirs_only: bool,
+    /// Metadata listed in columns before the tree, only by the [`TreeFormat::Ascii`],
+    /// [`TreeFormat::Plain`] and [`TreeFormat::MarkdownBlock`] formats
+    pub columns: Vec<Column>,
+    /// Regular expression of the matched parts of names to highlight, only by the
+    /// [`TreeFormat::Ascii`] format
+    pub highlight: Option<String>,
+}
+
+impl Default for RenderOptions {
+    /// Whole tree with names only.
+    fn default() -> Self {
+        RenderOptions {
+            th_depth: -1,
+            show_size: false,
+            disk_usage: false,
+            show_count: false,
+            dirs_first: false,
+            dirs_only: false,
+            columns: Vec::new(),
+            highlight: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Whether the children of a folder at `depth` are rendered.
+    pub fn shows_children(&self, depth: i32) -> bool {
+        (self.th_depth < 0) || (depth < self.th_depth)
+    }
+
+    /// Whether anything is rendered that an access error makes unknown.
+    fn shows_content_info(&self) -> bool {
+        self.show_size || self.disk_usage || self.show_count
+    }
+}
+
+/// Counts as in the summary of `tree`, e.g. `1 directory, 3 files`, folders only with
+/// `dirs_only`, as in the summary of `tree -d`.
+pub fn counts_text(counts: Counts, dirs_only: bool) -> String {
+    let plural = |n: u64, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+    let dirs = plural(counts.dirs, "directory", "directories");
+    if dirs_only {
+        dirs
+    } else {
+        format!("{dirs}, {}", plural(counts.files, "file", "files"))
+    }
+}
+
+/// Metadata `tree` can list in columns before the tree.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    /// File type and permissions, e.g. drwxr-xr-x
+    Perm,
+    /// Name of the owning user
+    Owner,
+    /// Name of the owning group
+    Group,
+    /// Size, for folders of all their content
+    Size,
+    /// Time of the last modification in UTC
+    Mtime,
+    /// Inode number
+    Inode,
+    /// Number of hard links
+    Links,
+}
+
+/// Columns of the long listing, like listed by `ls -l`.
+pub const LONG_COLUMNS: [Column; 5] = [
+    Column::Perm,
+    Column::Owner,
+    Column::Group,
+    Column::Size,
+    Column::Mtime,
+];
+
+impl Column {
+    /// Whether the column holds numbers, which are aligned to the right.
+    fn is_numeric(self) -> bool {
+        matches!(self, Column::Size | Column::Inode | Column::Links)
+    }
+}
+
+/// Names of users and groups by their ids.
+#[derive(Default)]
+struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    fn read() -> OwnerNames {
+        OwnerNames {
+            users: read_names("/etc/passwd"),
+            groups: read_names("/etc/group"),
+        }
+    }
+}
+
+/// Names by id from a file with lines like `name:x:id:...`, empty if it cannot be read.
+fn read_names(path: &str) -> HashMap<u32, String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((id, name.to_string()))
+        })
+        .collect()
+}
+
+/// Mode bits as listed by `ls -l`, e.g. `drwxr-xr-x`.
+fn mode_text(mode: u32) -> String {
+    let kind = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o010000 => 'p',
+        0o140000 => 's',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        _ => '-',
+    };
+    let mut text = String::from(kind);
+    // setuid, setgid and sticky bit replace the execute bit of user, group and others
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        text.push(if bits & 4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 2 != 0 { 'w' } else { '-' });
+        text.push(match (mode & special != 0, bits & 1 != 0) {
+            (true, true) => special_char,
+            (true, false) => special_char.to_ascii_uppercase(),
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+/// Text of a column for an entry, `?` if not available.
+fn column_text(column: Column, entry: Entry, names: &OwnerNames) -> String {
+    let (size, modified, inode, num_links, mode, owner) = match entry {
+        Entry::File(file) => (
+            file.size,
+            file.modified,
+            file.inode,
+            file.num_links,
+            file.mode,
+            file.owner,
+        ),
+        Entry::Dir(dir) => (
+            dir.size,
+            dir.modified,
+            dir.inode,
+            dir.num_links,
+            dir.mode,
+            dir.owner,
+        ),
+    };
+    let name = |names: &HashMap<u32, String>, id: u32| {
+        names.get(&id).cloned().unwrap_or_else(|| id.to_string())
+    };
+    let text = match column {
+        Column::Perm => mode.map(mode_text),
+        Column::Owner => owner.map(|(uid, _)| name(&names.users, uid)),
+        Column::Group => owner.map(|(_, gid)| name(&names.groups, gid)),
+        Column::Size => size.map(size_text),
+        Column::Mtime => modified.map(time2str),
+        Column::Inode => inode.map(|(_, ino)| ino.to_string()),
+        Column::Links => num_links.map(|n| n.to_string()),
+    };
+    text.unwrap_or_else(|| String::from("?"))
+}
+
+/// Name followed by the target if it is a symbolic link, e.g. `latest -> v1.2`.
+fn with_target(name: &str, link: Option<&LinkTarget>, escape: impl Fn(&str) -> String) -> String {
+    match link {
+        Some(link) => format!(
+            "{} -> {}",
+            escape(name),
+            escape(&link.target.to_string_lossy())
+        ),
+        None => escape(name),
+    }
+}
+
+/// Output format of a scanned tree.
+pub trait TreeRenderer {
+    /// Render a tree, the same tree can be rendered by several renderers.
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String;
+}
+
+/// Formats `tree` can print.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum TreeFormat {
+    /// Tree with connectors and colors
+    Ascii,
+    /// Tree with connectors, without colors
+    Plain,
+    /// Nested JSON object
+    Json,
+    /// One JSON object per entry and line
+    Ndjson,
+    /// One row per entry
+    Csv,
+    /// Standalone HTML page with collapsible folders
+    Html,
+    /// Nested Markdown list
+    Markdown,
+    /// Markdown code block holding the plain tree
+    MarkdownBlock,
+}
+
+impl TreeFormat {
+    pub fn renderer(self) -> Box<dyn TreeRenderer> {
+        match self {
+            TreeFormat::Ascii => Box::new(AsciiRenderer::colored()),
+            TreeFormat::Plain => Box::new(AsciiRenderer::plain()),
+            TreeFormat::Json => Box::new(JsonRenderer),
+            TreeFormat::Ndjson => Box::new(RowRenderer::Ndjson),
+            TreeFormat::Csv => Box::new(RowRenderer::Csv),
+            TreeFormat::Html => Box::new(HtmlRenderer),
+            TreeFormat::Markdown => Box::new(MarkdownRenderer::List),
+            TreeFormat::MarkdownBlock => Box::new(MarkdownRenderer::Block),
+        }
+    }
+
+    /// Whether the output is meant for programs or files, so nothing else may be printed.
+    pub fn is_machine_readable(self) -> bool {
+        !matches!(self, TreeFormat::Ascii | TreeFormat::Plain)
+    }
+}
+
+/// Options of a single ASCII rendering, passed down while walking the tree.
+struct AsciiContext<'o> {
+    options: &'o RenderOptions,
+    /// Highlights matches of [`RenderOptions::highlight`], styles nothing if unused
+    styler_match: Styler,
+}
+
+/// Tree drawn with connectors, one entry per line.
+pub struct AsciiRenderer {
+    /// Whether to highlight matches of [`RenderOptions::highlight`]
+    highlight: bool,
+    styler_size: Styler,
+    styler_allocated: Styler,
+    styler_folder: Styler,
+    styler_error: Styler,
+}
+
+impl AsciiRenderer {
+    pub fn colored() -> AsciiRenderer {
+        AsciiRenderer {
+            highlight: true,
+            styler_size: Styler::build("cyan", "", false, false, "").unwrap(),
+            styler_allocated: Styler::build("green", "", false, false, "").unwrap(),
+            styler_folder: Styler::build("yellow", "", false, false, "").unwrap(),
+            styler_error: Styler::build("red", "", false, false, "").unwrap(),
+        }
+    }
+
+    pub fn plain() -> AsciiRenderer {
+        let styler = || Styler::build("", "", false, false, "").unwrap();
+        AsciiRenderer {
+            highlight: false,
+            styler_size: styler(),
+            styler_allocated: styler(),
+            styler_folder: styler(),
+            styler_error: styler(),
+        }
+    }
+
+    fn render_file<'a>(
+        &self,
+        file: &'a FileEntry,
+        prefix: &str,
+        connector: &str,
+        context: &AsciiContext,
+        lines: &mut Vec<(Entry<'a>, String)>,
+    ) {
+        let options = context.options;
+        let mut out = String::new();
+        let name = match &file.link {
+            Some(link) if link.broken => format!(
+                "{}{}",
+                context
+                    .styler_match
+                    .style_within(&file.name(), &self.styler_error),
+                self.styler_error
+                    .style(&format!(" -> {}", link.target.to_string_lossy()))
+            ),
+            _ => with_target(
+                &context.styler_match.style(&file.name()),
+                file.link.as_ref(),
+                str::to_string,
+            ),
+        };
+        out.push_str(&format!("{}{}{}", prefix, connector, name));
+        if options.show_size {
+            if let Some(size) = file.size {
+                out.push_str(&format!(" {:6}", bites2str(size, &self.styler_size)));
+            }
+        }
+        if options.disk_usage {
+            if let Some(allocated) = file.allocated {
+                out.push_str(&format!(
+                    " {:6}",
+                    bites2str(allocated, &self.styler_allocated)
+                ));
+            }
+        }
+        lines.push((Entry::File(file), out));
+    }
+
+    fn render_dir<'a>(
+        &self,
+        dir: &'a DirEntry,
+        prefix: &str,
+        connector: &str,
+        depth: i32,
+        context: &AsciiContext,
+        lines: &mut Vec<(Entry<'a>, String)>,
+    ) {
+        let options = context.options;
+        let mut out = String::new();
+        out.push_str(&format!(
+            "{}{}{}",
+            prefix,
+            connector,
+            context
+                .styler_match
+                .style_within(&dir.name(), &self.styler_folder),
+        ));
+        if let Some(link) = &dir.link {
+            out.push_str(&format!(" -> {}", link.target.to_string_lossy()));
+        }
+
+        if dir.cycle {
+            out.push_str(&format!("  {}", self.styler_error.style(CYCLE_NOTE)));
+        } else if options.shows_content_info() && !dir.have_access {
+            out.push_str(&format!("  {}", self.styler_error.style("access error")));
+        } else {
+            if options.show_size {
+                let size_suffix = match dir.size {
+                    Some(c) => format!(" {:6}", bites2str(c, &self.styler_size)),
+                    None => self.styler_size.style(" size unknown"),
+                };
+                out.push_str(size_suffix.as_str());
+            }
+            if options.disk_usage {
+                if let Some(allocated) = dir.allocated {
+                    out.push_str(&format!(
+                        " {:6}",
+                        bites2str(allocated, &self.styler_allocated)
+                    ));
+                }
+            }
+            if let Some(counts) = dir.counts.filter(|_| options.show_count) {
+                out.push_str(&format!(" [{}]", counts_text(counts, options.dirs_only)));
+            }
+        }
+        lines.push((Entry::Dir(dir), out));
+
+        if !options.shows_children(depth) {
+            return;
+        }
+        let child_prefix = match connector {
+            TEE => format!("{prefix}{PIPE_PREFIX}"),
+            ELBOW => format!("{prefix}{SPACE_PREFIX}"),
+            _ => prefix.to_string(),
+        };
+        let num_children = dir.children_file.len() + dir.children_dir.len();
+        for (num_child, child) in dir.children(options.dirs_first).enumerate() {
+            let connector = if num_child + 1 < num_children {
+                TEE
+            } else {
+                ELBOW
+            };
+            match child {
+                Entry::File(file) => {
+                    self.render_file(file, &child_prefix, connector, context, lines)
+                }
+                Entry::Dir(child) => {
+                    self.render_dir(child, &child_prefix, connector, depth + 1, context, lines)
+                }
+            }
+        }
+    }
+}
+
+impl TreeRenderer for AsciiRenderer {
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String {
+        let mut lines = Vec::new();
+        let styler_match = options
+            .highlight
+            .as_ref()
+            .filter(|_| self.highlight)
+            .and_then(|pattern| Styler::build("cyan", "", false, true, pattern).ok())
+            .unwrap_or_else(|| Styler::build("", "", false, false, "").unwrap());
+        let context = AsciiContext {
+            options,
+            styler_match,
+        };
+        self.render_dir(root, "", "", 0, &context, &mut lines);
+
+        let names = if options.columns.contains(&Column::Owner)
+            || options.columns.contains(&Column::Group)
+        {
+            OwnerNames::read()
+        } else {
+            OwnerNames::default()
+        };
+        let cells: Vec<Vec<String>> = lines
+            .iter()
+            .map(|(entry, _)| {
+                options
+                    .columns
+                    .iter()
+                    .map(|column| column_text(*column, *entry, &names))
+                    .collect()
+            })
+            .collect();
+        let widths: Vec<usize> = (0..options.columns.len())
+            .map(|i| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut out = String::new();
+        for ((_, text), row) in lines.iter().zip(cells) {
+            for ((cell, column), width) in row.iter().zip(&options.columns).zip(&widths) {
+                let padded = if column.is_numeric() {
+                    format!("{cell:>width$}")
+                } else {
+                    format!("{cell:<width$}")
+                };
+                match column {
+                    Column::Size => out.push_str(&self.styler_size.style(&padded)),
+                    _ => out.push_str(&padded),
+                }
+                out.push_str("  ");
+            }
+            out.push_str(text);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Quote text as JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Fields shared by all JSON formats, without enclosing braces.
+fn json_fields(row: &Row, options: &RenderOptions) -> String {
+    let size = match row.size {
+        Some(size) if options.show_size => size.to_string(),
+        _ => String::from("null"),
+    };
+    let allocated = match row.allocated {
+        Some(allocated) if options.disk_usage => allocated.to_string(),
+        _ => String::from("null"),
+    };
+    let target = row.link.map_or(String::from("null"), |link| {
+        json_string(&link.target.to_string_lossy())
+    });
+    let num_files = match row.counts {
+        Some(counts) if options.show_count && !options.dirs_only => counts.files.to_string(),
+        _ => String::from("null"),
+    };
+    let num_dirs = match row.counts {
+        Some(counts) if options.show_count => counts.dirs.to_string(),
+        _ => String::from("null"),
+    };
+    format!(
+        "\"name\":{},\"path\":{},\"type\":\"{}\",\"size\":{},\"allocated\":{},\"access_error\":{},\"target\":{},\"cycle\":{},\"num_files\":{},\"num_dirs\":{}",
+        json_string(&row.name),
+        json_string(&row.path.to_string_lossy()),
+        if row.is_dir { "dir" } else { "file" },
+        size,
+        allocated,
+        row.access_error,
+        target,
+        row.cycle,
+        num_files,
+        num_dirs,
+    )
+}
+
+/// Quote a CSV field if needed.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Nested JSON object per folder, listing files and folders as `children`.
+pub struct JsonRenderer;
+
+impl JsonRenderer {
+    fn render_file(&self, file: &FileEntry, options: &RenderOptions, out: &mut String) {
+        let fields = json_fields(&Row::file(file, 0, None), options);
+        out.push_str(&format!("{{{fields}}}"));
+    }
+
+    fn render_dir(&self, dir: &DirEntry, depth: i32, options: &RenderOptions, out: &mut String) {
+        let fields = json_fields(&Row::dir(dir, depth, None), options);
+        out.push_str(&format!("{{{fields},\"children\":["));
+        if options.shows_children(depth) {
+            for (num_child, child) in dir.children(options.dirs_first).enumerate() {
+                if num_child > 0 {
+                    out.push(',');
+                }
+                match child {
+                    Entry::File(file) => self.render_file(file, options, out),
+                    Entry::Dir(child) => self.render_dir(child, depth + 1, options, out),
+                }
+            }
+        }
+        out.push_str("]}");
+    }
+}
+
+impl TreeRenderer for JsonRenderer {
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String {
+        let mut out = String::new();
+        self.render_dir(root, 0, options, &mut out);
+        out.push('\n');
+        out
+    }
+}
+
+/// Flat entry of a tree, as listed by [`RowRenderer`].
+struct Row<'a> {
+    name: Cow<'a, str>,
+    path: &'a Path,
+    is_dir: bool,
+    depth: i32,
+    parent: Option<&'a Path>,
+    size: Option<u64>,
+    allocated: Option<u64>,
+    access_error: bool,
+    link: Option<&'a LinkTarget>,
+    cycle: bool,
+    counts: Option<Counts>,
+}
+
+impl<'a> Row<'a> {
+    fn file(file: &'a FileEntry, depth: i32, parent: Option<&'a Path>) -> Row<'a> {
+        Row {
+            name: file.name(),
+            path: &file.path,
+            is_dir: false,
+            depth,
+            parent,
+            size: file.size,
+            allocated: file.allocated,
+            access_error: false,
+            link: file.link.as_ref(),
+            cycle: false,
+            counts: None,
+        }
+    }
+
+    fn dir(dir: &'a DirEntry, depth: i32, parent: Option<&'a Path>) -> Row<'a> {
+        Row {
+            name: dir.name(),
+            path: &dir.path,
+            is_dir: true,
+            depth,
+            parent,
+            size: dir.size,
+            allocated: dir.allocated,
+            access_error: !dir.have_access,
+            link: dir.link.as_ref(),
+            cycle: dir.cycle,
+            counts: dir.counts,
+        }
+    }
+}
+
+/// One row per entry with its depth and parent path, folders before their content.
+pub enum RowRenderer {
+    Ndjson,
+    Csv,
+}
+
+const CSV_HEADER: &str =
+    "name,path,type,depth,parent,size,allocated,access_error,target,cycle,num_files,num_dirs";
+
+impl RowRenderer {
+    fn collect_rows<'a>(
+        dir: &'a DirEntry,
+        depth: i32,
+        parent: Option<&'a Path>,
+        options: &RenderOptions,
+        rows: &mut Vec<Row<'a>>,
+    ) {
+        rows.push(Row::dir(dir, depth, parent));
+        if !options.shows_children(depth) {
+            return;
+        }
+        for child in dir.children(options.dirs_first) {
+            match child {
+                Entry::File(file) => rows.push(Row::file(file, depth + 1, Some(&dir.path))),
+                Entry::Dir(child) => {
+                    RowRenderer::collect_rows(child, depth + 1, Some(&dir.path), options, rows)
+                }
+            }
+        }
+    }
+
+    fn format_row(&self, row: &Row, options: &RenderOptions) -> String {
+        let parent = row.parent.map(|p| p.to_string_lossy());
+        match self {
+            RowRenderer::Ndjson => {
+                let fields = json_fields(row, options);
+                let parent = parent.map_or(String::from("null"), |p| json_string(&p));
+                format!("{{{fields},\"depth\":{},\"parent\":{parent}}}", row.depth)
+            }
+            RowRenderer::Csv => {
+                let size = match row.size {
+                    Some(size) if options.show_size => size.to_string(),
+                    _ => String::new(),
+                };
+                let allocated = match row.allocated {
+                    Some(allocated) if options.disk_usage => allocated.to_string(),
+                    _ => String::new(),
+                };
+                let target = row.link.map_or(String::new(), |link| {
+                    csv_field(&link.target.to_string_lossy())
+                });
+                let num_files = match row.counts {
+                    Some(counts) if options.show_count && !options.dirs_only => {
+                        counts.files.to_string()
+                    }
+                    _ => String::new(),
+                };
+                let num_dirs = match row.counts {
+                    Some(counts) if options.show_count => counts.dirs.to_string(),
+                    _ => String::new(),
+                };
+                format!(
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    csv_field(&row.name),
+                    csv_field(&row.path.to_string_lossy()),
+                    if row.is_dir { "dir" } else { "file" },
+                    row.depth,
+                    parent.map_or(String::new(), |p| csv_field(&p)),
+                    size,
+                    allocated,
+                    row.access_error,
+                    target,
+                    row.cycle,
+                    num_files,
+                    num_dirs,
+                )
+            }
+        }
+    }
+}
+
+impl TreeRenderer for RowRenderer {
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String {
+        let mut rows = Vec::new();
+        RowRenderer::collect_rows(root, 0, None, options, &mut rows);
+
+        let mut out = String::new();
+        if let RowRenderer::Csv = self {
+            out.push_str(CSV_HEADER);
+            out.push('\n');
+        }
+        for row in &rows {
+            out.push_str(&self.format_row(row, options));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Size as text without padding or colors, e.g. `2.00 KB`.
+fn size_text(size: u64) -> String {
+    let styler = Styler::build("", "", false, false, "").unwrap();
+    bites2str(size, &styler).trim().to_string()
+}
+
+/// Escape text for HTML content and attributes.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>TITLE</title>
+<style>
+body { font-family: monospace; }
+ul { list-style: none; padding-left: 1.5em; margin: 0; }
+summary { cursor: pointer; }
+.dir { color: #b58900; font-weight: bold; }
+.size { color: #2aa198; margin-left: 1em; }
+.error { color: #dc322f; margin-left: 1em; }
+.broken { color: #dc322f; }
+.bar { display: inline-block; height: 0.7em; margin-left: 1em; background: #268bd2; }
+</style>
+</head>
+<body>
+"#;
+
+const HTML_TAIL: &str = "</body>\n</html>\n";
+
+/// Standalone HTML page, folders can be collapsed and show bars proportional to their
+/// share of the parent folder.
+pub struct HtmlRenderer;
+
+impl HtmlRenderer {
+    fn size_html(
+        (size, allocated): (Option<u64>, Option<u64>),
+        parent_size: Option<u64>,
+        options: &RenderOptions,
+    ) -> String {
+        let mut html = String::new();
+        if let Some(allocated) = allocated.filter(|_| options.disk_usage) {
+            html.push_str(&format!(
+                "<span class=\"size\">{} on disk</span>",
+                size_text(allocated)
+            ));
+        }
+        let Some(size) = size.filter(|_| options.show_size) else {
+            return html;
+        };
+        html.insert_str(
+            0,
+            &format!("<span class=\"size\">{}</span>", size_text(size)),
+        );
+        // without the parent's size there is nothing to compare to, so no bar is drawn
+        let share = match parent_size {
+            Some(0) => 0.,
+            Some(parent_size) => size as f64 / parent_size as f64,
+            None => return html,
+        };
+        html.push_str(&format!(
+            "<span class=\"bar\" style=\"width: {:.1}em\"></span>",
+            share * 10.
+        ));
+        html
+    }
+
+    fn render_dir(
+        &self,
+        dir: &DirEntry,
+        depth: i32,
+        parent_size: Option<u64>,
+        options: &RenderOptions,
+        out: &mut String,
+    ) {
+        let open = if depth == 0 { " open" } else { "" };
+        out.push_str(&format!(
+            "<details{open}><summary><span class=\"dir\">{}</span>",
+            with_target(&dir.name(), dir.link.as_ref(), html_escape)
+        ));
+        if dir.cycle {
+            out.push_str(&format!("<span class=\"error\">{CYCLE_NOTE}</span>"));
+        } else if options.shows_content_info() && !dir.have_access {
+            out.push_str("<span class=\"error\">access error</span>");
+        } else {
+            out.push_str(&HtmlRenderer::size_html(
+                (dir.size, dir.allocated),
+                parent_size,
+                options,
+            ));
+            if let Some(counts) = dir.counts.filter(|_| options.show_count) {
+                out.push_str(&format!(
+                    "<span class=\"size\">{}</span>",
+                    counts_text(counts, options.dirs_only)
+                ));
+            }
+        }
+        out.push_str("</summary>\n");
+
+        if options.shows_children(depth) {
+            out.push_str("<ul>\n");
+            for child in dir.children(options.dirs_first) {
+                match child {
+                    Entry::File(file) => {
+                        let name = with_target(&file.name(), file.link.as_ref(), html_escape);
+                        let name = match &file.link {
+                            Some(link) if link.broken => {
+                                format!("<span class=\"broken\">{name}</span>")
+                            }
+                            _ => name,
+                        };
+                        out.push_str(&format!(
+                            "<li>{}{}</li>\n",
+                            name,
+                            HtmlRenderer::size_html((file.size, file.allocated), dir.size, options)
+                        ))
+                    }
+                    Entry::Dir(child) => {
+                        out.push_str("<li>");
+                        self.render_dir(child, depth + 1, dir.size, options, out);
+                        out.push_str("</li>\n");
+                    }
+                }
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</details>");
+    }
+}
+
+impl TreeRenderer for HtmlRenderer {
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String {
+        let title = html_escape(&root.path.to_string_lossy());
+        let mut out = HTML_HEAD.replace("TITLE", &title);
+        self.render_dir(root, 0, root.size, options, &mut out);
+        out.push('\n');
+        out.push_str(HTML_TAIL);
+        out
+    }
+}
+
+/// Markdown for READMEs, either as nested list or as code block.
+pub enum MarkdownRenderer {
+    List,
+    Block,
+}
+
+/// Escape characters Markdown would interpret in names.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '#') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Sizes and counts in parentheses, e.g. ` (2.00 KB, 4.10 KB on disk)`.
+fn markdown_size(
+    (size, allocated): (Option<u64>, Option<u64>),
+    counts: Option<Counts>,
+    options: &RenderOptions,
+) -> String {
+    let mut notes = Vec::new();
+    if options.show_size {
+        notes.push(size.map_or(String::from("size unknown"), size_text));
+    }
+    if let Some(allocated) = allocated.filter(|_| options.disk_usage) {
+        notes.push(format!("{} on disk", size_text(allocated)));
+    }
+    if let Some(counts) = counts.filter(|_| options.show_count) {
+        notes.push(counts_text(counts, options.dirs_only));
+    }
+    if notes.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", notes.join(", "))
+    }
+}
+
+impl MarkdownRenderer {
+    fn render_list(&self, dir: &DirEntry, depth: i32, options: &RenderOptions, out: &mut String) {
+        let indent = "  ".repeat(depth as usize);
+        out.push_str(&format!("{indent}- **{}/**", markdown_escape(&dir.name())));
+        if let Some(link) = &dir.link {
+            out.push_str(&format!(
+                " -> {}",
+                markdown_escape(&link.target.to_string_lossy())
+            ));
+        }
+        if dir.cycle {
+            out.push_str(&format!(" ({CYCLE_NOTE})"));
+        } else if options.shows_content_info() && !dir.have_access {
+            out.push_str(" (access error)");
+        } else {
+            out.push_str(&markdown_size(
+                (dir.size, dir.allocated),
+                dir.counts,
+                options,
+            ));
+        }
+        out.push('\n');
+        if !options.shows_children(depth) {
+            return;
+        }
+        for child in dir.children(options.dirs_first) {
+            match child {
+                Entry::File(file) => {
+                    out.push_str(&format!(
+                        "{indent}  - {}",
+                        with_target(&file.name(), file.link.as_ref(), markdown_escape)
+                    ));
+                    if file.link.as_ref().is_some_and(|link| link.broken) {
+                        out.push_str(" (broken link)");
+                    }
+                    out.push_str(&markdown_size((file.size, file.allocated), None, options));
+                    out.push('\n');
+                }
+                Entry::Dir(child) => self.render_list(child, depth + 1, options, out),
+            }
+        }
+    }
+}
+
+impl TreeRenderer for MarkdownRenderer {
+    fn render(&self, root: &DirEntry, options: &RenderOptions) -> String {
+        let mut out = String::new();
+        match self {
+            MarkdownRenderer::List => self.render_list(root, 0, options, &mut out),
+            MarkdownRenderer::Block => {
+                out.push_str("```text\n");
+                out.push_str(&AsciiRenderer::plain().render(root, options));
+                out.push_str("```\n");
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test_render {
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        AsciiRenderer, Column, HtmlRenderer, JsonRenderer, MarkdownRenderer, RenderOptions,
+        RowRenderer, TreeRenderer,
+    };
+    use crate::functions::tree::{DirEntry, FileEntry};
+
+    fn file(path: &str, size: u64) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from(path),
+            size: Some(size),
+            allocated: Some(4096),
+            modified: None,
+            inode: None,
+            num_links: None,
+            mode: None,
+            owner: None,
+            link: None,
+        }
+    }
+
+    fn get_tree() -> DirEntry {
+        let sub = DirEntry {
+            path: PathBuf::from("root/sub"),
+            have_access: true,
+            children_file: vec![file("root/sub/b.txt", 2000)],
+            children_dir: Vec::new(),
+            size: Some(2000),
+            allocated: Some(8192),
+            counts: None,
+            modified: None,
+            inode: None,
+            num_links: None,
+            mode: None,
+            owner: None,
+            truncated: false,
+            link: None,
+            cycle: false,
+        };
+        let locked = DirEntry {
+            path: PathBuf::from("root/locked"),
+            have_access: false,
+            children_file: Vec::new(),
+            children_dir: Vec::new(),
+            size: None,
+            allocated: None,
+            counts: None,
+            modified: None,
+            inode: None,
+            num_links: None,
+            mode: None,
+            owner: None,
+            truncated: false,
+            link: None,
+            cycle: false,
+        };
+        DirEntry {
+            path: PathBuf::from("root"),
+            have_access: true,
+            children_file: vec![file("root/a.txt", 10)],
+            children_dir: vec![locked, sub],
+            size: None,
+            allocated: None,
+            counts: None,
+            modified: None,
+            inode: None,
+            num_links: None,
+            mode: None,
+            owner: None,
+            truncated: false,
+            link: None,
+            cycle: false,
+        }
+    }
+
+    #[test]
+    fn same_tree_different_depths() {
+        let tree = get_tree();
+        let renderer = AsciiRenderer::plain();
+        let options = RenderOptions {
+            show_size: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            renderer.render(&tree, &options),
+            "\
+root size unknown
+├── a.txt   10.00 B
+├── locked  access error
+└── sub    2.00 KB
+    └── b.txt    2.00 KB
+"
+        );
+        let options = RenderOptions {
+            th_depth: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            renderer.render(&tree, &options),
+            "root\n├── a.txt\n├── locked\n└── sub\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let options = RenderOptions {
+            show_size: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            JsonRenderer.render(&get_tree(), &options),
+            "{\"name\":\"root\",\"path\":\"root\",\"type\":\"dir\",\"size\":null,\"allocated\":null,\"access_error\":false,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null,\"children\":[\
+             {\"name\":\"a.txt\",\"path\":\"root/a.txt\",\"type\":\"file\",\"size\":10,\"allocated\":null,\"access_error\":false,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null},\
+             {\"name\":\"locked\",\"path\":\"root/locked\",\"type\":\"dir\",\"size\":null,\"allocated\":null,\"access_error\":true,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null,\"children\":[]},\
+             {\"name\":\"sub\",\"path\":\"root/sub\",\"type\":\"dir\",\"size\":2000,\"allocated\":null,\"access_error\":false,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null,\"children\":[\
+             {\"name\":\"b.txt\",\"path\":\"root/sub/b.txt\",\"type\":\"file\",\"size\":2000,\"allocated\":null,\"access_error\":false,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null}]}]}\n"
+        );
+    }
+
+    #[test]
+    fn rows() {
+        let options = RenderOptions {
+            show_size: true,
+            ..Default::default()
+        };
+        let ndjson = RowRenderer::Ndjson.render(&get_tree(), &options);
+        let lines: Vec<&str> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[4],
+            "{\"name\":\"b.txt\",\"path\":\"root/sub/b.txt\",\"type\":\"file\",\"size\":2000,\"allocated\":null,\"access_error\":false,\"target\":null,\"cycle\":false,\"num_files\":null,\"num_dirs\":null,\"depth\":2,\"parent\":\"root/sub\"}"
+        );
+
+        let options = RenderOptions::default();
+        assert_eq!(
+            RowRenderer::Csv.render(&get_tree(), &options),
+            "\
+name,path,type,depth,parent,size,allocated,access_error,target,cycle,num_files,num_dirs
+root,root,dir,0,,,,false,,false,,
+a.txt,root/a.txt,file,1,root,,,false,,false,,
+locked,root/locked,dir,1,root,,,true,,false,,
+sub,root/sub,dir,1,root,,,false,,false,,
+b.txt,root/sub/b.txt,file,2,root/sub,,,false,,false,,
+"
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            super::json_string("a\"b\\c\n\u{1}"),
+            "\"a\\\"b\\\\c\\n\\u0001\""
+        );
+        assert_eq!(super::csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+        assert_eq!(super::csv_field("plain"), "plain");
+    }
+
+    #[test]
+    fn html() {
+        let options = RenderOptions {
+            show_size: true,
+            ..Default::default()
+        };
+        let html = HtmlRenderer.render(&get_tree(), &options);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.ends_with("</html>\n"));
+        assert!(html.contains("<details open><summary><span class=\"dir\">root</span>"));
+        assert!(html.contains("<span class=\"error\">access error</span>"));
+        // sub holds all of its content, so its file gets the full bar
+        assert!(html.contains(
+            "<li>b.txt<span class=\"size\">2.00 KB</span>\
+             <span class=\"bar\" style=\"width: 10.0em\"></span></li>"
+        ));
+        // the size of root is unknown, so its file gets no bar
+        assert!(html.contains("<li>a.txt<span class=\"size\">10.00 B</span></li>"));
+        assert_eq!(html.matches("<details").count(), 3);
+        assert_eq!(html.matches("</details>").count(), 3);
+    }
+
+    #[test]
+    fn markdown() {
+        let options = RenderOptions {
+            show_size: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            MarkdownRenderer::List.render(&get_tree(), &options),
+            "\
+- **root/** (size unknown)
+  - a.txt (10.00 B)
+  - **locked/** (access error)
+  - **sub/** (2.00 KB)
+    - b.txt (2.00 KB)
+"
+        );
+        let options = RenderOptions {
+            th_depth: 1,
+            disk_usage: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            MarkdownRenderer::List.render(&get_tree(), &options),
+            "\
+- **root/**
+  - a.txt (4.10 KB on disk)
+  - **locked/** (access error)
+  - **sub/** (8.19 KB on disk)
+"
+        );
+        let options = RenderOptions {
+            th_depth: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            MarkdownRenderer::Block.render(&get_tree(), &options),
+            "```text\nroot\n├── a.txt\n├── locked\n└── sub\n```\n"
+        );
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(super::mode_text(0o40755), "drwxr-xr-x");
+        assert_eq!(super::mode_text(0o100644), "-rw-r--r--");
+        assert_eq!(super::mode_text(0o120777), "lrwxrwxrwx");
+        assert_eq!(super::mode_text(0o41777), "drwxrwxrwt");
+        assert_eq!(super::mode_text(0o104644), "-rwSr--r--");
+
+        let mut tree = get_tree();
+        tree.mode = Some(0o40755);
+        tree.num_links = Some(4);
+        tree.children_file[0].mode = Some(0o100644);
+        tree.children_file[0].num_links = Some(12);
+        tree.children_file[0].modified = Some(UNIX_EPOCH + Duration::from_secs(1709312700));
+        let options = RenderOptions {
+            th_depth: 1,
+            columns: vec![Column::Perm, Column::Links, Column::Size, Column::Mtime],
+            ..Default::default()
+        };
+        assert_eq!(
+            AsciiRenderer::plain().render(&tree, &options),
+            "\
+drwxr-xr-x   4        ?  ?                 root
+-rw-r--r--  12  10.00 B  2024-03-01 17:05  ├── a.txt
+?            ?        ?  ?                 ├── locked
+?            ?  2.00 KB  ?                 └── sub
+"
+        );
+    }
+
+    #[test]
+    fn highlight() {
+        let options = RenderOptions {
+            highlight: Some(String::from("txt")),
+            ..Default::default()
+        };
+        let colored = AsciiRenderer::colored().render(&get_tree(), &options);
+        assert!(colored.contains("── a.\x1b[4;96mtxt\x1b[0m\n"));
+        assert!(colored.contains("── b.\x1b[4;96mtxt\x1b[0m\n"));
+        // the folder color is kept around matches
+        let options = RenderOptions {
+            highlight: Some(String::from("u")),
+            ..Default::default()
+        };
+        let colored = AsciiRenderer::colored().render(&get_tree(), &options);
+        assert!(colored.contains("── \x1b[33ms\x1b[0m\x1b[4;96mu\x1b[0m\x1b[33mb\x1b[0m\n"));
+        // plain output is never highlighted
+        let plain = AsciiRenderer::plain().render(&get_tree(), &options);
+        assert!(plain.contains("── a.txt\n"));
+    }
+}
@@ -0,0 +1,31 @@
+//! Command line tools for files: renaming, editing, searching and listing them.
+//!
+//! Besides the `ruclifs` binary, the logic can be used as a library. The commands
+//! compute data and leave printing to the caller:
+//! * [`RenamePlan`]: plan and execute renamings of files
+//! * [`scan`]: scan a folder into a tree of [`DirEntry`] and [`FileEntry`], printed
+//!   by a [`TreeRenderer`]
+//! * [`disk_usage`]: largest files and folders of a scanned tree
+//! * [`Script`] and [`sed_stream`]: the sed engine, editing text line by line
+mod functions;
+mod utils;
+
+pub use functions::du::{disk_usage, DiskUsage, OtherUsage, UsageEntry};
+pub use functions::rename::{RenamePlan, Renaming};
+pub use functions::sed::{detect_encoding, sed_stream, Change};
+pub use functions::sed_script::Script;
+pub use functions::tree::{
+    scan, Counts, DirEntry, Entry, FileEntry, LinkTarget, ScanOptions, SortKey, TreeFilter,
+};
+pub use functions::tree_render::{Column, RenderOptions, TreeFormat, TreeRenderer};
+pub use utils::encoding::Encoding;
+pub use utils::error::RuclifsError;
+
+// commands as run by the binary, printing their results
+pub use functions::du::{du, DuArgs};
+pub use functions::grep::{grep, GrepArgs};
+pub use functions::rename::{rename, RenameArgs};
+pub use functions::sed::{sed, SedArgs};
+pub use functions::tree::{build_tree, TreeArgs};
+pub use utils::cli::Confirmation;
+pub use utils::error::get_exit_code;
@@ -1,18 +1,33 @@
-mod functions;
-mod utils;
 use std::process;
 
 use clap::{Parser, Subcommand};
 
-use crate::functions::rename::{rename, RenameArgs};
-use crate::functions::sed::SedArgs;
-use crate::functions::tree::{build_tree, TreeArgs};
+use ruclifs::{
+    build_tree, du, get_exit_code, grep, rename, sed, Confirmation, DuArgs, GrepArgs, RenameArgs,
+    RuclifsError, SedArgs, TreeArgs,
+};
+
+/// Listed in the help, matches RuclifsError::exit_code
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  other errors
+  2  invalid usage
+  3  invalid regex
+  4  glob error
+  5  IO error
+  6  aborted by the user";
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_CODES)]
 struct MainArgs {
     #[command(subcommand)]
     cmd: Commands,
+    /// Answer confirmations with yes, needed to modify files when stdin is no terminal
+    #[arg(short = 'y', long = "yes", global = true, conflicts_with = "no")]
+    yes: bool,
+    /// Answer confirmations with no, e.g. to only see the preview
+    #[arg(long = "no", global = true)]
+    no: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -21,37 +36,109 @@ enum Commands {
     Ren(RenameArgs),
     ///Awesome Streaming editor
     Sed(SedArgs),
+    ///Searching files for a pattern
+    Grep(GrepArgs),
+    ///Listing the largest files and folders
+    Du(DuArgs),
     // copy(CopyArgs),
     // delete(DeleteArgs),
     Tree(TreeArgs),
 }
 
 fn main() {
-    let version: &str = "0.0.0";
-    println!("\x1b[90mThis is ruclifs version {version}\x1b[0m");
-
     let args = MainArgs::parse();
 
-    match &args.cmd {
+    // keep stdout clean when the output is used in a pipeline
+    let clean_stdout = match &args.cmd {
+        Commands::Sed(cmd_args) => cmd_args.is_filter_mode(),
+        Commands::Tree(cmd_args) => cmd_args.format.is_machine_readable(),
+        _ => false,
+    };
+    if !clean_stdout {
+        let version: &str = "0.0.0";
+        println!("\x1b[90mThis is ruclifs version {version}\x1b[0m");
+    }
+
+    let confirmation = Confirmation::from_flags(args.yes, args.no);
+
+    let (result, action) = match &args.cmd {
         Commands::Ren(cmd_args) => {
             println!("{:?}", cmd_args);
-            if let Err(e) = rename(
+            let result = rename(
                 &cmd_args.path,
                 &cmd_args.filter_string,
                 &cmd_args.pattern,
                 &cmd_args.substitute,
                 cmd_args.recursive,
                 cmd_args.skip_preview,
-            ) {
-                println!("Error when renaming: {e}");
-                process::exit(1);
-            }
+                cmd_args.preserve_case,
+                confirmation,
+            );
+            (result, "renaming")
         }
         Commands::Sed(cmd_args) => {
-            println!("{:?}", cmd_args)
+            let result = cmd_args.get_script().and_then(|script| {
+                sed(
+                    &cmd_args.path,
+                    &cmd_args.filter_string,
+                    &script,
+                    cmd_args.recursive,
+                    cmd_args.skip_preview,
+                    cmd_args.max_preview,
+                    cmd_args.binary,
+                    cmd_args.jobs,
+                    cmd_args.interactive,
+                    confirmation,
+                )
+            });
+            (result, "editing")
+        }
+        Commands::Du(cmd_args) => {
+            let result = du(
+                &cmd_args.path,
+                cmd_args.top,
+                cmd_args.threshold,
+                cmd_args.threads,
+            );
+            (result, "measuring disk usage")
+        }
+        Commands::Grep(cmd_args) => {
+            let result = grep(
+                &cmd_args.path,
+                &cmd_args.filter_string,
+                &cmd_args.pattern,
+                cmd_args.recursive,
+                cmd_args.before.unwrap_or(cmd_args.context),
+                cmd_args.after.unwrap_or(cmd_args.context),
+                cmd_args.count,
+                cmd_args.binary,
+                cmd_args.jobs,
+            );
+            (result, "searching")
         }
         Commands::Tree(cmd_args) => {
-            build_tree(&cmd_args.path, cmd_args.depth, !cmd_args.hide_size);
+            let result = cmd_args.get_filter().and_then(|filter| {
+                build_tree(
+                    &cmd_args.path,
+                    &cmd_args.get_scan_options(),
+                    &filter,
+                    &cmd_args.get_render_options(),
+                    cmd_args.format,
+                    cmd_args.sort,
+                    cmd_args.reverse,
+                )
+            });
+            (result, "building the tree")
+        }
+    };
+
+    // exit codes are only decided here, see RuclifsError::exit_code
+    if let Err(e) = result {
+        if let Some(RuclifsError::UserAbort) = e.downcast_ref::<RuclifsError>() {
+            println!("Will abort here. See you soon!");
+        } else {
+            eprintln!("Error when {action}: {e:#}");
         }
+        process::exit(get_exit_code(&e));
     }
 }
@@ -0,0 +1,112 @@
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+
+#[derive(Debug, PartialEq)]
+enum CaseStyle {
+    Lower,
+    Upper,
+    Capitalized,
+    Other,
+}
+
+fn get_case_style(text: &str) -> CaseStyle {
+    let mut letters = text.chars().filter(|c| c.is_alphabetic());
+    let Some(first) = letters.next() else {
+        return CaseStyle::Other;
+    };
+    let rest: Vec<char> = letters.collect();
+    let rest_lower = rest.iter().all(|c| c.is_lowercase());
+    let rest_upper = rest.iter().all(|c| c.is_uppercase());
+
+    if first.is_uppercase() && rest_upper && !rest.is_empty() {
+        CaseStyle::Upper
+    } else if first.is_uppercase() && rest_lower {
+        CaseStyle::Capitalized
+    } else if first.is_lowercase() && rest_lower {
+        CaseStyle::Lower
+    } else {
+        CaseStyle::Other
+    }
+}
+
+fn map_first_char(text: &str, map: impl Fn(char) -> String) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => map(first) + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+/// #### Adapt the casing of a replacement to the casing of the text it replaces.
+///
+/// Upper case matches give upper case replacements, capitalized matches capitalized
+/// ones and lower case matches start the replacement in lower case. The replacement is
+/// kept as is for mixed casing.
+pub fn match_case(matched: &str, replacement: &str) -> String {
+    match get_case_style(matched) {
+        CaseStyle::Upper => replacement.to_uppercase(),
+        CaseStyle::Capitalized => map_first_char(replacement, |c| c.to_uppercase().collect()),
+        CaseStyle::Lower => map_first_char(replacement, |c| c.to_lowercase().collect()),
+        CaseStyle::Other => replacement.to_string(),
+    }
+}
+
+/// #### Replace matches of a regex, keeping the casing of each matched text.
+///
+/// ##### Arguments
+/// * `regex`: Compiled pattern, usually case-insensitive
+/// * `text`: Text to replace in
+/// * `limit`: Maximum number of replacements, 0 for all
+/// * `substitute`: Replacement, may reference capture groups
+pub fn replace_preserving_case<'t>(
+    regex: &Regex,
+    text: &'t str,
+    limit: usize,
+    substitute: &str,
+) -> Cow<'t, str> {
+    regex.replacen(text, limit, |caps: &Captures| {
+        let mut replacement = String::new();
+        caps.expand(substitute, &mut replacement);
+        match_case(&caps[0], &replacement)
+    })
+}
+
+#[cfg(test)]
+mod test_case {
+    use regex::Regex;
+
+    use super::{match_case, replace_preserving_case};
+
+    #[test]
+    fn casing_styles() {
+        assert_eq!(match_case("user", "account"), "account");
+        assert_eq!(match_case("User", "account"), "Account");
+        assert_eq!(match_case("USER", "account"), "ACCOUNT");
+        assert_eq!(match_case("uSeR", "account"), "account");
+        assert_eq!(match_case("user", "MyAccount"), "myAccount");
+        assert_eq!(match_case("_", "account"), "account");
+    }
+
+    #[test]
+    fn identifiers() {
+        let regex = Regex::new("(?i)user").unwrap();
+        let text = "userId UserId USER_ID";
+        assert_eq!(
+            replace_preserving_case(&regex, text, 0, "account"),
+            "accountId AccountId ACCOUNT_ID"
+        );
+        assert_eq!(
+            replace_preserving_case(&regex, text, 1, "account"),
+            "accountId UserId USER_ID"
+        );
+    }
+
+    #[test]
+    fn capture_groups() {
+        let regex = Regex::new("(?i)get_(user)").unwrap();
+        assert_eq!(
+            replace_preserving_case(&regex, "GET_USER", 0, "fetch_$1"),
+            "FETCH_USER"
+        );
+    }
+}
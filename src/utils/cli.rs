@@ -1,7 +1,8 @@
+use crate::utils::error::RuclifsError;
 use anyhow::{anyhow, Result};
 use regex::Regex;
-use std::io;
-use std::process;
+use std::io::{self, IsTerminal, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub const INDENT: &str = "    ";
 
@@ -23,8 +24,8 @@ impl Styler {
         pattern: &str,
     ) -> Result<Styler> {
         // do nothing if no options are chosen
-        if (color_fg == "" || color_fg == "default")
-            && (color_bg == "" || color_bg == "default")
+        if (color_fg.is_empty() || color_fg == "default")
+            && (color_bg.is_empty() || color_bg == "default")
             && !bold
             && !underline
         {
@@ -69,22 +70,20 @@ impl Styler {
         let style_seq = format!("\x1b[{}m", style_str);
 
         // get regex
-        if pattern != "" {
-            let style_regex = Regex::new(&format!("({})", pattern)).unwrap_or_else(|err| {
-                println!("Problem when compiling the regex pattern: {err}");
-                process::exit(1)
-            });
-            return Ok(Styler {
+        if !pattern.is_empty() {
+            let style_regex =
+                Regex::new(&format!("({})", pattern)).map_err(RuclifsError::InvalidRegex)?;
+            Ok(Styler {
                 style_seq: style_seq.to_owned(),
                 reset_seq: String::from("\x1b[0m"),
                 regex: Some(style_regex),
-            });
+            })
         } else {
-            return Ok(Styler {
+            Ok(Styler {
                 style_seq: style_seq.to_owned(),
                 reset_seq: String::from("\x1b[0m"),
                 regex: None,
-            });
+            })
         }
     }
 
@@ -96,22 +95,189 @@ impl Styler {
                 .into_owned(),
         }
     }
+
+    /// #### Style the matches of the pattern, and the text around them with another styler.
+    ///
+    /// Styling the result of [`Styler::style`] again would end the outer style at the
+    /// first match, since the reset sequence resets all styles.
+    ///
+    /// ##### Arguments
+    /// * `text`: Text to style
+    /// * `styler_outer`: Styler for the text around matches, all text without a pattern
+    pub fn style_within(&self, text: &str, styler_outer: &Styler) -> String {
+        let Some(re) = &self.regex else {
+            return styler_outer.style(text);
+        };
+        let mut styled = String::new();
+        let mut last_end: usize = 0;
+        for found in re.find_iter(text).filter(|found| !found.is_empty()) {
+            if found.start() > last_end {
+                styled.push_str(&styler_outer.style(&text[last_end..found.start()]));
+            }
+            styled.push_str(&format!(
+                "{}{}{}",
+                &self.style_seq,
+                found.as_str(),
+                &self.reset_seq
+            ));
+            last_end = found.end();
+        }
+        if last_end < text.len() {
+            styled.push_str(&styler_outer.style(&text[last_end..]));
+        }
+        styled
+    }
+}
+
+/// How confirmations before modifying files are answered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Confirmation {
+    /// Ask the user, which needs stdin to be a terminal
+    Ask,
+    /// Proceed without asking
+    Yes,
+    /// Abort without asking
+    No,
+}
+
+impl Confirmation {
+    /// Confirmation matching the global `--yes` and `--no` flags.
+    pub fn from_flags(yes: bool, no: bool) -> Confirmation {
+        match (yes, no) {
+            (true, _) => Confirmation::Yes,
+            (_, true) => Confirmation::No,
+            _ => Confirmation::Ask,
+        }
+    }
+}
+
+/// Fail unless stdin is a terminal the user can answer questions in.
+pub fn ensure_terminal() -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "stdin is not a terminal, pass --yes to proceed without confirmation"
+        ));
+    }
+    Ok(())
 }
 
-pub fn proceed_query(text: &str) {
+/// #### Ask the user whether to proceed.
+///
+/// ##### Arguments
+/// * `text`: Question shown to the user
+/// * `confirmation`: Whether to ask or answer right away
+/// * `return`: Whether the user agreed, an error if stdin can not be asked
+pub fn proceed_query(text: &str, confirmation: Confirmation) -> Result<bool> {
     println!("\n{}", text);
+    match confirmation {
+        Confirmation::Yes => {
+            println!("yes (--yes)");
+            return Ok(true);
+        }
+        Confirmation::No => {
+            println!("no (--no)");
+            return Ok(false);
+        }
+        Confirmation::Ask => ensure_terminal()?,
+    }
     let mut input = String::new();
-    match io::stdin().read_line(&mut input) {
-        Ok(_) => {
-            if input.trim() != "yes" && input.trim() != "y" {
-                println!("Will abort here. See you soon!");
-                std::process::exit(0)
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(|err| anyhow!("Problem reading the input: {err}"))?;
+    Ok(input.trim() == "yes" || input.trim() == "y")
+}
+
+/// #### Check that files may be modified without asking, e.g. when the preview is skipped.
+///
+/// ##### Arguments
+/// * `confirmation`: How confirmations are answered
+/// * `return`: An error if refused by `--no`, or if stdin is not a terminal and
+///   `--yes` was not given
+pub fn ensure_unattended(confirmation: Confirmation) -> Result<()> {
+    match confirmation {
+        Confirmation::Yes => Ok(()),
+        Confirmation::No => Err(RuclifsError::UserAbort.into()),
+        Confirmation::Ask => ensure_terminal(),
+    }
+}
+
+/// #### Ask the user to pick one of several choices.
+///
+/// The question is repeated until the answer starts with one of the choices.
+///
+/// ##### Arguments
+/// * `text`: Question shown to the user
+/// * `choices`: Characters the user may answer with
+/// * `return`: Chosen character
+pub fn query_choice(text: &str, choices: &str) -> Result<char> {
+    loop {
+        print!("{} ", text);
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Err(anyhow!("No answer, stdin was closed"));
+        }
+        if let Some(choice) = input.trim().to_lowercase().chars().next() {
+            if choices.contains(choice) {
+                return Ok(choice);
             }
         }
-        Err(err) => println!("Problem reading the input: {err}"),
     }
 }
 
+/// #### Ask the user for a line of text.
+///
+/// ##### Arguments
+/// * `text`: Question shown to the user
+/// * `return`: Trimmed answer, empty if stdin was closed
+pub fn query_input(text: &str) -> Result<String> {
+    println!("\n{}", text);
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+/// #### Parse a selection of entries like "-3,7-9,12".
+///
+/// Entries are numbered from 1. Open ranges like "-3" or "7-" start at the first or
+/// end at the last entry.
+///
+/// ##### Arguments
+/// * `text`: Comma separated indices and ranges
+/// * `num_entries`: Number of entries to select from
+/// * `return`: Zero-based indices of the selected entries, in the given order
+pub fn parse_selection(text: &str, num_entries: usize) -> Result<Vec<usize>> {
+    let parse_index = |part: &str, default: usize| -> Result<usize> {
+        let part = part.trim();
+        let idx = if part.is_empty() {
+            default
+        } else {
+            part.parse::<usize>()
+                .map_err(|_| anyhow!("Not a valid index: {}", part))?
+        };
+        if idx == 0 || idx > num_entries {
+            return Err(anyhow!("Index out of range 1-{}: {}", num_entries, idx));
+        }
+        Ok(idx)
+    };
+
+    let mut indices: Vec<usize> = Vec::new();
+    for part in text.split(',').filter(|p| !p.trim().is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (parse_index(start, 1)?, parse_index(end, num_entries)?),
+            None => {
+                let idx = parse_index(part, 0)?;
+                (idx, idx)
+            }
+        };
+        if start > end {
+            return Err(anyhow!("Range is empty: {}", part.trim()));
+        }
+        indices.extend((start - 1)..end);
+    }
+    Ok(indices)
+}
+
 /// #### Format data size in bites to nicely readable units.
 ///
 /// ##### Arguments
@@ -144,7 +310,72 @@ pub fn bites2str(size: u64, styler: &Styler) -> String {
         unit = "PB";
         unit_size = (fsize / base.powf(5.)) as f32;
     }
-    return styler.style(format!("{:7.2} {}", unit_size, unit).as_str());
+    styler.style(format!("{:7.2} {}", unit_size, unit).as_str())
+}
+
+/// #### Parse a data size as written by [`bites2str`], e.g. `100MB` or `1.5 gb`.
+///
+/// Units are powers of 1000 and optional, a number alone is a size in bites.
+///
+/// ##### Arguments
+/// * `text`: Number with an optional unit B, KB, MB, GB, TB or PB
+/// * `return`: Size in bites
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let idx_unit = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(idx_unit);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {text}"))?;
+    let exponent = match unit.trim().to_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return Err(format!("Unknown unit of size: {}", unit.trim())),
+    };
+    Ok((number * 1000f64.powi(exponent)).round() as u64)
+}
+
+/// #### Format a point in time as date and minute in UTC, e.g. `2024-03-01 17:05`.
+///
+/// ##### Arguments
+/// * `time`: Point in time, can be before 1970
+/// * `return`: Date and time
+pub fn time2str(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // civil date from days since 1970, in eras of 400 years starting in March
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60
+    )
 }
 
 #[cfg(test)]
@@ -205,12 +436,92 @@ mod test_styler {
             styler.style("some_string")
         );
     }
+
+    #[test]
+    fn regex_within() {
+        let styler = Styler::build("cyan", "", false, true, "me_st").unwrap();
+        let styler_outer = Styler::build("yellow", "", false, false, "").unwrap();
+        assert_eq!(
+            "\u{1b}[33mso\u{1b}[0m\u{1b}[4;96mme_st\u{1b}[0m\u{1b}[33mring\u{1b}[0m",
+            styler.style_within("some_string", &styler_outer)
+        );
+        let styler_plain = Styler::build("", "", false, false, "").unwrap();
+        assert_eq!(
+            "\u{1b}[33msome_string\u{1b}[0m",
+            styler_plain.style_within("some_string", &styler_outer)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_proceed_query {
+
+    use super::{proceed_query, Confirmation};
+
+    #[test]
+    fn answered_by_flags() {
+        assert!(proceed_query("Proceed?", Confirmation::Yes).unwrap());
+        assert!(!proceed_query("Proceed?", Confirmation::No).unwrap());
+    }
+
+    #[test]
+    fn from_flags() {
+        assert_eq!(Confirmation::from_flags(false, false), Confirmation::Ask);
+        assert_eq!(Confirmation::from_flags(true, false), Confirmation::Yes);
+        assert_eq!(Confirmation::from_flags(false, true), Confirmation::No);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_selection {
+
+    use super::parse_selection;
+
+    #[test]
+    fn indices_and_ranges() {
+        assert_eq!(parse_selection("2", 10).unwrap(), vec![1]);
+        assert_eq!(
+            parse_selection("-3,7-9", 10).unwrap(),
+            vec![0, 1, 2, 6, 7, 8]
+        );
+        assert_eq!(parse_selection(" 9- , 1", 10).unwrap(), vec![8, 9, 0]);
+    }
+
+    #[test]
+    fn invalid_selection() {
+        assert!(parse_selection("0", 10).is_err());
+        assert!(parse_selection("11", 10).is_err());
+        assert!(parse_selection("5-3", 10).is_err());
+        assert!(parse_selection("a", 10).is_err());
+    }
 }
 
 #[cfg(test)]
 mod test_bytes2str {
 
-    use super::{bites2str, Styler};
+    use super::{bites2str, parse_size, time2str, Styler};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_time2str() {
+        assert_eq!(time2str(UNIX_EPOCH), "1970-01-01 00:00");
+        let time = UNIX_EPOCH + Duration::from_secs(1709312700);
+        assert_eq!(time2str(time), "2024-03-01 17:05");
+        let time = UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(time2str(time), "2000-02-29 00:00");
+        let time = UNIX_EPOCH - Duration::from_secs(60);
+        assert_eq!(time2str(time), "1969-12-31 23:59");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_size("1.5 gb"), Ok(1_500_000_000));
+        assert_eq!(parse_size("20k"), Ok(20_000));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert!(parse_size("12XB").is_err());
+        assert!(parse_size("MB").is_err());
+    }
 
     #[test]
     fn test_bytes2str() {
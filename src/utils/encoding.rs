@@ -0,0 +1,303 @@
+use std::io::{self, BufRead};
+
+/// Number of bytes looked at to guess the kind of a file.
+pub const SAMPLE_SIZE: usize = 8192;
+
+const BOM_UTF8: &[u8] = &[0xEF, 0xBB, 0xBF];
+const BOM_UTF16LE: &[u8] = &[0xFF, 0xFE];
+const BOM_UTF16BE: &[u8] = &[0xFE, 0xFF];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8 { bom: bool },
+    Utf16Le { bom: bool },
+    Utf16Be { bom: bool },
+    Latin1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Text(Encoding),
+    Binary,
+}
+
+/// #### Guess encoding of a text or whether it is binary from its first bytes.
+///
+/// BOMs are trusted. Without BOM, UTF-16 is recognized by the zero bytes of ASCII
+/// characters, which would otherwise mark the sample as binary (NUL-byte heuristic).
+/// Zeros have to be much more frequent in one byte position than in the other, since
+/// characters like U+0100 or U+4E00 have zero bytes in the other position as well.
+/// Text that is no valid UTF-8 is assumed to be Latin-1.
+pub fn detect(sample: &[u8]) -> FileKind {
+    if sample.starts_with(BOM_UTF8) {
+        return FileKind::Text(Encoding::Utf8 { bom: true });
+    } else if sample.starts_with(BOM_UTF16LE) {
+        return FileKind::Text(Encoding::Utf16Le { bom: true });
+    } else if sample.starts_with(BOM_UTF16BE) {
+        return FileKind::Text(Encoding::Utf16Be { bom: true });
+    }
+
+    if !sample.contains(&0) {
+        return match std::str::from_utf8(sample) {
+            Ok(_) => FileKind::Text(Encoding::Utf8 { bom: false }),
+            // sample may end in the middle of a character
+            Err(e) if e.error_len().is_none() => FileKind::Text(Encoding::Utf8 { bom: false }),
+            Err(_) => FileKind::Text(Encoding::Latin1),
+        };
+    }
+
+    let num_units = sample.len() / 2;
+    if num_units > 0 {
+        let zeros_even = sample.iter().step_by(2).filter(|b| **b == 0).count();
+        let zeros_odd = sample
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count();
+        let is_utf16 = |zeros_high: usize, zeros_low: usize, encoding: Encoding| {
+            zeros_high > 0 && zeros_low * 4 <= zeros_high && utf16_valid(sample, encoding)
+        };
+        let (le, be) = (
+            Encoding::Utf16Le { bom: false },
+            Encoding::Utf16Be { bom: false },
+        );
+        if is_utf16(zeros_odd, zeros_even, le) {
+            return FileKind::Text(le);
+        } else if is_utf16(zeros_even, zeros_odd, be) {
+            return FileKind::Text(be);
+        }
+    }
+    FileKind::Binary
+}
+
+/// #### Check that a sample decodes as UTF-16 text in the given byte order.
+///
+/// The sample may not contain control characters below tab or unpaired surrogates,
+/// apart from a surrogate cut off at its end.
+fn utf16_valid(sample: &[u8], encoding: Encoding) -> bool {
+    let units: Vec<u16> = sample
+        .chunks_exact(2)
+        .map(|c| match encoding {
+            Encoding::Utf16Be { .. } => u16::from_be_bytes([c[0], c[1]]),
+            _ => u16::from_le_bytes([c[0], c[1]]),
+        })
+        .collect();
+    let units = match units.last() {
+        Some(0xD800..=0xDBFF) => &units[..units.len() - 1],
+        _ => &units[..],
+    };
+    char::decode_utf16(units.iter().copied())
+        .all(|c| matches!(c, Ok(c) if !('\u{1}'..='\u{8}').contains(&c)))
+}
+
+impl Encoding {
+    /// Byte order mark the encoded text starts with, empty if there is none.
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 { bom: true } => BOM_UTF8,
+            Encoding::Utf16Le { bom: true } => BOM_UTF16LE,
+            Encoding::Utf16Be { bom: true } => BOM_UTF16BE,
+            _ => b"",
+        }
+    }
+
+    /// #### Read one raw line including its line ending.
+    ///
+    /// ##### Arguments
+    /// * `reader`: Source of the encoded text
+    /// * `buffer`: Line bytes are appended here
+    /// * `return`: Number of bytes read, 0 at the end of the input
+    pub fn read_line<R: BufRead>(&self, reader: &mut R, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        match self {
+            Encoding::Utf8 { .. } | Encoding::Latin1 => reader.read_until(b'\n', buffer),
+            Encoding::Utf16Le { .. } | Encoding::Utf16Be { .. } => {
+                let start = buffer.len();
+                loop {
+                    if reader.read_until(b'\n', buffer)? == 0 {
+                        break;
+                    }
+                    let len = buffer.len() - start;
+                    // the high byte of a little endian line feed follows the matched byte
+                    if matches!(self, Encoding::Utf16Le { .. }) && !len.is_multiple_of(2) {
+                        let mut next = [0u8; 1];
+                        if reader.read(&mut next)? == 0 {
+                            break;
+                        }
+                        buffer.push(next[0]);
+                    }
+                    let len = buffer.len() - start;
+                    if len.is_multiple_of(2)
+                        && buffer[buffer.len() - 2..] == self.encode_unit(b'\n')
+                    {
+                        break;
+                    }
+                }
+                Ok(buffer.len() - start)
+            }
+        }
+    }
+
+    fn encode_unit(&self, byte: u8) -> [u8; 2] {
+        match self {
+            Encoding::Utf16Be { .. } => [0, byte],
+            _ => [byte, 0],
+        }
+    }
+
+    /// #### Split a raw line into its content and its line ending.
+    ///
+    /// The line ending ("\n", "\r\n" or none for the last line) is returned as is,
+    /// so it can be written back unchanged.
+    pub fn split_line_ending<'a>(&self, line: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        let (cr, lf): (&[u8], &[u8]) = match self {
+            Encoding::Utf8 { .. } | Encoding::Latin1 => (b"\r", b"\n"),
+            Encoding::Utf16Le { .. } => (&[b'\r', 0], &[b'\n', 0]),
+            Encoding::Utf16Be { .. } => (&[0, b'\r'], &[0, b'\n']),
+        };
+        if !line.ends_with(lf) {
+            return (line, b"");
+        }
+        let content = &line[..line.len() - lf.len()];
+        if content.ends_with(cr) {
+            line.split_at(content.len() - cr.len())
+        } else {
+            line.split_at(content.len())
+        }
+    }
+
+    /// Decode raw bytes, `None` if they are not valid in this encoding.
+    pub fn decode(&self, raw: &[u8]) -> Option<String> {
+        match self {
+            Encoding::Utf8 { .. } => std::str::from_utf8(raw).ok().map(String::from),
+            Encoding::Latin1 => Some(raw.iter().map(|b| *b as char).collect()),
+            Encoding::Utf16Le { .. } | Encoding::Utf16Be { .. } => {
+                if !raw.len().is_multiple_of(2) {
+                    return None;
+                }
+                let units: Vec<u16> = raw
+                    .chunks_exact(2)
+                    .map(|c| match self {
+                        Encoding::Utf16Be { .. } => u16::from_be_bytes([c[0], c[1]]),
+                        _ => u16::from_le_bytes([c[0], c[1]]),
+                    })
+                    .collect();
+                String::from_utf16(&units).ok()
+            }
+        }
+    }
+
+    /// Encode text, `None` if it contains characters this encoding can not represent.
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Utf8 { .. } => Some(text.as_bytes().to_vec()),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).ok())
+                .collect(),
+            Encoding::Utf16Le { .. } => {
+                Some(text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect())
+            }
+            Encoding::Utf16Be { .. } => {
+                Some(text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_encoding {
+    use std::io::Cursor;
+
+    use super::{detect, Encoding, FileKind};
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn detect_utf8() {
+        assert_eq!(
+            detect("some text with ümlauts".as_bytes()),
+            FileKind::Text(Encoding::Utf8 { bom: false })
+        );
+        assert_eq!(
+            detect(b"\xEF\xBB\xBFsome text"),
+            FileKind::Text(Encoding::Utf8 { bom: true })
+        );
+    }
+
+    #[test]
+    fn detect_latin1() {
+        assert_eq!(detect(b"some \xFCmlaut"), FileKind::Text(Encoding::Latin1));
+    }
+
+    #[test]
+    fn detect_utf16() {
+        assert_eq!(
+            detect(&utf16le("some text\r\n")),
+            FileKind::Text(Encoding::Utf16Le { bom: false })
+        );
+        let be: Vec<u8> = "some text"
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect();
+        assert_eq!(
+            detect(&be),
+            FileKind::Text(Encoding::Utf16Be { bom: false })
+        );
+        assert_eq!(
+            detect(b"\xFF\xFEs\x00"),
+            FileKind::Text(Encoding::Utf16Le { bom: true })
+        );
+    }
+
+    #[test]
+    fn detect_utf16_non_latin() {
+        // U+0100 and U+4E00 have a zero byte in the position of the other zeros
+        let text = "Āpfel und 一二三 sind keine Äpfel\n".repeat(20);
+        assert_eq!(
+            detect(&utf16le(&text)),
+            FileKind::Text(Encoding::Utf16Le { bom: false })
+        );
+        let be: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        assert_eq!(
+            detect(&be),
+            FileKind::Text(Encoding::Utf16Be { bom: false })
+        );
+    }
+
+    #[test]
+    fn detect_binary() {
+        assert_eq!(
+            detect(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x01"),
+            FileKind::Binary
+        );
+    }
+
+    #[test]
+    fn read_utf16_lines() {
+        let encoding = Encoding::Utf16Le { bom: false };
+        // U+0A0A has line feed bytes but is no line ending
+        let raw = utf16le("first\r\nsec\u{0A0A}ond\nlast");
+        let mut reader = Cursor::new(raw);
+        let mut lines: Vec<String> = Vec::new();
+        let mut buffer: Vec<u8> = Vec::new();
+
+        while encoding.read_line(&mut reader, &mut buffer).unwrap() > 0 {
+            let (content, ending) = encoding.split_line_ending(&buffer);
+            lines.push(encoding.decode(content).unwrap() + &encoding.decode(ending).unwrap());
+            buffer.clear();
+        }
+        assert_eq!(lines, vec!["first\r\n", "sec\u{0A0A}ond\n", "last"]);
+    }
+
+    #[test]
+    fn latin1_round_trip() {
+        let encoding = Encoding::Latin1;
+        let text = encoding.decode(b"\xFCber").unwrap();
+        assert_eq!(text, "über");
+        assert_eq!(encoding.encode(&text).unwrap(), b"\xFCber");
+        assert_eq!(encoding.encode("€"), None);
+    }
+}
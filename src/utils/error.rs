@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors of ruclifs, each category exits with its own code.
+#[derive(Debug)]
+pub enum RuclifsError {
+    /// A regex pattern did not compile
+    InvalidRegex(regex::Error),
+    /// A glob pattern was invalid or files could not be collected
+    Glob(String),
+    /// Reading or writing a path failed
+    Io { path: PathBuf, source: io::Error },
+    /// The user chose not to proceed
+    UserAbort,
+}
+
+impl RuclifsError {
+    /// Wrap an IO error together with the path it occurred for, e.g. in `map_err`.
+    pub fn io(path: &Path) -> impl FnOnce(io::Error) -> RuclifsError + '_ {
+        move |source| RuclifsError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// #### Exit code of the process for this error.
+    ///
+    /// * 3: invalid regex
+    /// * 4: glob error
+    /// * 5: IO error
+    /// * 6: aborted by the user
+    ///
+    /// Errors of other kinds exit with 1, 2 is left to clap for invalid usage.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RuclifsError::InvalidRegex(_) => 3,
+            RuclifsError::Glob(_) => 4,
+            RuclifsError::Io { .. } => 5,
+            RuclifsError::UserAbort => 6,
+        }
+    }
+}
+
+impl fmt::Display for RuclifsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuclifsError::InvalidRegex(e) => {
+                write!(f, "Problem when compiling the regex pattern: {e}")
+            }
+            RuclifsError::Glob(e) => write!(f, "Problem with file collection: {e}"),
+            RuclifsError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            RuclifsError::UserAbort => write!(f, "Aborted by the user"),
+        }
+    }
+}
+
+// the cause is part of the message already, so it is not exposed as source as well
+impl std::error::Error for RuclifsError {}
+
+impl From<regex::Error> for RuclifsError {
+    fn from(e: regex::Error) -> Self {
+        RuclifsError::InvalidRegex(e)
+    }
+}
+
+/// Exit code for any error, 1 for errors not raised by ruclifs itself.
+pub fn get_exit_code(error: &anyhow::Error) -> i32 {
+    match error.downcast_ref::<RuclifsError>() {
+        Some(e) => e.exit_code(),
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod test_error {
+    use anyhow::{anyhow, Context, Result};
+    use regex::Regex;
+    use std::fs::File;
+    use std::path::Path;
+
+    use super::{get_exit_code, RuclifsError};
+
+    #[test]
+    #[allow(clippy::invalid_regex)]
+    fn exit_codes() {
+        let regex_error: Result<Regex> = Regex::new("(")
+            .map_err(RuclifsError::from)
+            .map_err(anyhow::Error::from);
+        assert_eq!(get_exit_code(&regex_error.unwrap_err()), 3);
+
+        let path = Path::new("/some/missing/file.txt");
+        let io_error = File::open(path)
+            .map_err(RuclifsError::io(path))
+            .unwrap_err();
+        assert!(io_error.to_string().starts_with("/some/missing/file.txt: "));
+        assert_eq!(get_exit_code(&io_error.into()), 5);
+
+        let abort: anyhow::Error = RuclifsError::UserAbort.into();
+        assert_eq!(get_exit_code(&abort.context("when renaming")), 6);
+
+        assert_eq!(get_exit_code(&anyhow!("something else")), 1);
+    }
+
+    #[test]
+    fn keeps_code_with_context() {
+        let result: Result<()> = Err(RuclifsError::Glob(String::from("bad")).into());
+        let error = result.context("Invalid glob").unwrap_err();
+        assert_eq!(get_exit_code(&error), 4);
+    }
+}
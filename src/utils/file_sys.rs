@@ -1,55 +1,138 @@
-use glob::glob;
+use crate::utils::error::RuclifsError;
+use crate::utils::parallel::walk_parallel;
+use anyhow::Result;
+use glob::{MatchOptions, Pattern};
 use regex::Regex;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::process;
 
 struct MockPaths {
     pub taken: Vec<PathBuf>,
     pub free: Vec<PathBuf>,
 }
 
-pub fn get_files(dir: &Path, glob_pattern: &str, recursive: bool) -> Vec<PathBuf> {
-    let full_glob_pattern = if recursive == true {
-        PathBuf::from(dir).join("**")
+/// #### Get the files in a folder matching a glob pattern.
+///
+/// Folders are read on `jobs` threads. Paths that are not valid UTF-8 can not be
+/// matched exactly, those matching the pattern with invalid parts replaced are reported
+/// as error instead of being left out.
+///
+/// ##### Arguments
+/// * `dir`: Folder to look in
+/// * `glob_pattern`: Pattern for the path of files relative to `dir`
+/// * `recursive`: Whether to look into subfolders
+/// * `jobs`: Number of threads, 0 or 1 reads everything on the current thread
+/// * `return`: Sorted list of files
+pub fn get_files(
+    dir: &Path,
+    glob_pattern: &str,
+    recursive: bool,
+    jobs: usize,
+) -> Result<Vec<PathBuf>> {
+    let full_glob_pattern = if recursive {
+        format!("**/{glob_pattern}")
     } else {
-        PathBuf::from(dir)
+        glob_pattern.to_string()
+    };
+    let pattern = Pattern::new(&full_glob_pattern)
+        .map_err(|e| RuclifsError::Glob(format!("{}: {}", glob_pattern, e)))?;
+    // components are matched one by one, like done for the files on disk by glob
+    let match_options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    // without a recursive wildcard, subfolders named by the pattern are read at most
+    let max_depth = if full_glob_pattern.contains("**") {
+        None
+    } else {
+        Some(glob_pattern.split('/').count() - 1)
     };
 
-    let full_glob_pattern = full_glob_pattern.join(glob_pattern);
-
-    let mut files: Vec<PathBuf> = Vec::new();
-
-    for entry in glob(full_glob_pattern.to_str().unwrap()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(file_path) => {
-                if file_path.is_file() {
-                    files.push(file_path.clone())
-                }
-            }
+    let mut listings = walk_parallel(vec![(dir.to_path_buf(), 0)], jobs, |(path, depth)| {
+        let content: Vec<PathBuf> = match fs::read_dir(&path) {
+            Ok(c) => c.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
             Err(e) => {
-                println!("Problem with file collection: {:?}.\nAborting.", e);
-                process::exit(1)
+                let error = RuclifsError::io(&path)(e);
+                return ((path, Err(error)), Vec::new());
+            }
+        };
+        let (dirs, files): (Vec<PathBuf>, Vec<PathBuf>) =
+            content.into_iter().partition(|path| path.is_dir());
+        let mut matched: Vec<PathBuf> = Vec::new();
+        for file in files.into_iter().filter(|file| file.is_file()) {
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            if relative.to_str().is_none()
+                && pattern.matches_with(&relative.to_string_lossy(), match_options)
+            {
+                let error =
+                    RuclifsError::Glob(format!("{}: path is not valid UTF-8", file.display()));
+                return ((path, Err(error)), Vec::new());
+            }
+            if pattern.matches_path_with(relative, match_options) {
+                matched.push(file);
             }
         }
+        let next = match max_depth {
+            Some(max_depth) if depth >= max_depth => Vec::new(),
+            _ => dirs.into_iter().map(|dir| (dir, depth + 1)).collect(),
+        };
+        ((path, Ok(matched)), next)
+    });
+    // report the same error whatever folder was read first
+    listings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for (_, listing) in listings {
+        files.extend(listing?);
     }
     files.sort();
-    files
+    Ok(files)
+}
+
+/// #### Get the files to process for a path that is either a single file or a folder.
+///
+/// ##### Arguments
+/// * `path`: File or folder
+/// * `glob_pattern`: Filter for files in a folder, ignored for a single file
+/// * `recursive`: Whether to look into subfolders
+/// * `jobs`: Number of threads reading folders
+/// * `return`: Sorted list of files
+pub fn get_files_in_path(
+    path: &Path,
+    glob_pattern: &str,
+    recursive: bool,
+    jobs: usize,
+) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        Ok(vec![path.to_path_buf()])
+    } else if path.is_dir() {
+        get_files(path, glob_pattern, recursive, jobs)
+    } else {
+        let source = io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        Err(RuclifsError::io(path)(source).into())
+    }
 }
 
 pub struct UniquePathGetter {
     mock_paths: MockPaths, // mimic taken and free paths when running dry
     num_regex: Regex,      // we only compile at construct time
 }
+impl Default for UniquePathGetter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl UniquePathGetter {
     /// Initializes a UniquePathGetter with empty mocking no taken or free paths.
     pub fn new() -> UniquePathGetter {
-        return UniquePathGetter {
+        UniquePathGetter {
             mock_paths: MockPaths {
                 taken: Vec::new(),
                 free: Vec::new(),
             },
             num_regex: Regex::new(r"_(\d*)$").unwrap(),
-        };
+        }
     }
 
     pub fn add_mock_taken(&mut self, path: PathBuf) {
@@ -61,8 +144,8 @@ impl UniquePathGetter {
     }
     pub fn get_unique(&self, path_in: &PathBuf) -> PathBuf {
         let file_stem_in = path_in.file_stem().unwrap().to_str().unwrap();
-        if (path_in.exists() || self.mock_paths.taken.contains(&path_in))
-            && !self.mock_paths.free.contains(&path_in)
+        if (path_in.exists() || self.mock_paths.taken.contains(path_in))
+            && !self.mock_paths.free.contains(path_in)
         {
             let mut name_count: i32;
 
@@ -103,9 +186,9 @@ impl UniquePathGetter {
                 file_name_new = file_stem_bare.clone() + &format!("_{}{}", name_count, file_ext);
                 path_out = path_in.parent().unwrap().join(&file_name_new);
             }
-            return path_out;
+            path_out
         } else {
-            return path_in.to_path_buf();
+            path_in.to_path_buf()
         }
     }
 }
@@ -161,3 +244,68 @@ mod test_get_unique_path {
         assert_eq!(path_out, path_in)
     }
 }
+
+#[cfg(test)]
+mod test_get_files {
+    use std::fs::{self, create_dir_all};
+    use tempfile::tempdir;
+
+    use super::get_files;
+
+    #[test]
+    fn files_matching_pattern() {
+        let tempdir = tempdir().unwrap();
+        let dir = tempdir.path();
+        create_dir_all(dir.join("sub").join("deeper")).unwrap();
+        for file in ["a.rs", "b.txt", "sub/c.rs", "sub/deeper/d.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let names = |files: Vec<std::path::PathBuf>| {
+            files
+                .iter()
+                .map(|f| {
+                    f.strip_prefix(dir)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect::<Vec<String>>()
+        };
+        for jobs in [1, 4] {
+            assert_eq!(
+                names(get_files(dir, "*.rs", false, jobs).unwrap()),
+                ["a.rs"]
+            );
+            assert_eq!(
+                names(get_files(dir, "*.rs", true, jobs).unwrap()),
+                ["a.rs", "sub/c.rs", "sub/deeper/d.rs"]
+            );
+            assert_eq!(
+                names(get_files(dir, "sub/*.rs", false, jobs).unwrap()),
+                ["sub/c.rs"]
+            );
+        }
+        assert!(get_files(dir, "[", false, 1).is_err());
+
+        tempdir.close().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_reported() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tempdir = tempdir().unwrap();
+        let dir = tempdir.path();
+        fs::write(dir.join("a.rs"), "").unwrap();
+        fs::write(dir.join(OsStr::from_bytes(b"b_\xFF.txt")), "").unwrap();
+
+        assert_eq!(get_files(dir, "*.rs", false, 1).unwrap().len(), 1);
+        let error = get_files(dir, "*", false, 1).unwrap_err();
+        assert!(error.to_string().contains("not valid UTF-8"));
+
+        tempdir.close().unwrap();
+    }
+}
@@ -1,2 +1,6 @@
+pub mod case;
 pub mod cli;
+pub mod encoding;
+pub mod error;
 pub mod file_sys;
+pub mod parallel;
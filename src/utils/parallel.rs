@@ -0,0 +1,274 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Number of jobs used when none are given, one per available CPU.
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// #### Process items on a pool of threads and consume the results in input order.
+///
+/// Results that finish early are held back until all results before them were consumed,
+/// so output stays deterministic. Processing stops at the first error of `consume`,
+/// results computed ahead of it are dropped without being consumed. At most `2 * jobs`
+/// results are computed ahead, so a slow item does not let finished results pile up.
+///
+/// ##### Arguments
+/// * `items`: Items to process
+/// * `jobs`: Number of threads, 0 or 1 processes everything on the current thread
+/// * `work`: Function processing a single item
+/// * `consume`: Called on the current thread with each result, in order of `items`
+pub fn for_each_ordered<T, R>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut consume: impl FnMut(R) -> Result<()>,
+) -> Result<()>
+where
+    T: Sync,
+    R: Send,
+{
+    if jobs <= 1 || items.len() <= 1 {
+        for item in items {
+            consume(work(item))?;
+        }
+        return Ok(());
+    }
+
+    let next_index = AtomicUsize::new(0);
+    // results consumed so far, workers wait with items too far ahead of it
+    let num_consumed = AtomicUsize::new(0);
+    let window = 2 * jobs;
+    let abort = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<(usize, R)>();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            let sender = sender.clone();
+            let (next_index, num_consumed, abort, work) =
+                (&next_index, &num_consumed, &abort, &work);
+            scope.spawn(move || {
+                let _guard = PanicGuard(abort);
+                loop {
+                    let index = next_index.fetch_add(1, Ordering::SeqCst);
+                    while index >= num_consumed.load(Ordering::SeqCst) + window
+                        && !abort.load(Ordering::SeqCst)
+                    {
+                        thread::sleep(Duration::from_micros(50));
+                    }
+                    if index >= items.len() || abort.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if sender.send((index, work(&items[index]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending: BTreeMap<usize, R> = BTreeMap::new();
+        let mut next_consumed: usize = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next_consumed) {
+                next_consumed += 1;
+                if let Err(e) = consume(result) {
+                    abort.store(true, Ordering::SeqCst);
+                    return Err(e);
+                }
+                num_consumed.store(next_consumed, Ordering::SeqCst);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Sets the flag when the thread panics, so other threads stop waiting for its work.
+struct PanicGuard<'a>(&'a AtomicBool);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Take the oldest item of another thread's queue.
+fn steal<T>(queues: &[Mutex<VecDeque<T>>], index: usize) -> Option<T> {
+    (1..queues.len())
+        .map(|offset| (index + offset) % queues.len())
+        .find_map(|other| queues[other].lock().unwrap().pop_front())
+}
+
+/// #### Process items that produce further items, e.g. folders, on work-stealing threads.
+///
+/// Every thread keeps a queue of its own and processes the newest item first. Threads
+/// running out of work take the oldest items queued by others. Results come in no
+/// particular order, so callers sort them if needed.
+///
+/// ##### Arguments
+/// * `roots`: Items to start with
+/// * `jobs`: Number of threads, 0 or 1 processes everything on the current thread
+/// * `work`: Function processing an item into a result and further items
+/// * `return`: Results of all items
+pub fn walk_parallel<T, R>(
+    roots: Vec<T>,
+    jobs: usize,
+    work: impl Fn(T) -> (R, Vec<T>) + Sync,
+) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+{
+    if jobs <= 1 {
+        let mut stack = roots;
+        let mut results = Vec::new();
+        while let Some(item) = stack.pop() {
+            let (result, more) = work(item);
+            results.push(result);
+            stack.extend(more);
+        }
+        return results;
+    }
+
+    let queues: Vec<Mutex<VecDeque<T>>> = (0..jobs).map(|_| Mutex::default()).collect();
+    // items queued or being processed, the walk is done when none are left
+    let num_pending = AtomicUsize::new(roots.len());
+    let abort = AtomicBool::new(false);
+    for (i, item) in roots.into_iter().enumerate() {
+        queues[i % jobs].lock().unwrap().push_back(item);
+    }
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..jobs)
+            .map(|index| {
+                let (queues, num_pending, abort, work) = (&queues, &num_pending, &abort, &work);
+                scope.spawn(move || {
+                    let _guard = PanicGuard(abort);
+                    let mut results = Vec::new();
+                    while !abort.load(Ordering::SeqCst) {
+                        let item = queues[index].lock().unwrap().pop_back();
+                        match item.or_else(|| steal(queues, index)) {
+                            Some(item) => {
+                                let (result, more) = work(item);
+                                results.push(result);
+                                // count new items before this one is done, not to end early
+                                num_pending.fetch_add(more.len(), Ordering::SeqCst);
+                                queues[index].lock().unwrap().extend(more);
+                                num_pending.fetch_sub(1, Ordering::SeqCst);
+                            }
+                            None if num_pending.load(Ordering::SeqCst) == 0 => break,
+                            None => thread::sleep(Duration::from_micros(50)),
+                        }
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test_parallel {
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use super::{for_each_ordered, walk_parallel};
+
+    #[test]
+    fn keeps_order() {
+        let items: Vec<u64> = (0..50).collect();
+        let mut results: Vec<u64> = Vec::new();
+        for_each_ordered(
+            &items,
+            4,
+            |i| {
+                sleep(Duration::from_millis((50 - i) % 7));
+                i * 2
+            },
+            |r| {
+                results.push(r);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn stops_at_error() {
+        let items: Vec<u64> = (0..50).collect();
+        let mut results: Vec<u64> = Vec::new();
+        let res = for_each_ordered(
+            &items,
+            4,
+            |i| *i,
+            |r| {
+                if r == 10 {
+                    return Err(anyhow!("failed at {r}"));
+                }
+                results.push(r);
+                Ok(())
+            },
+        );
+        assert!(res.is_err());
+        assert_eq!(results, (0..10).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn bounds_results_ahead() {
+        // the first item is slow, the others may only run a limited way ahead of it
+        let items: Vec<u64> = (0..100).collect();
+        let num_ahead = AtomicUsize::new(0);
+        let max_ahead = AtomicUsize::new(0);
+        let mut results: Vec<u64> = Vec::new();
+        for_each_ordered(
+            &items,
+            4,
+            |i| {
+                if *i == 0 {
+                    sleep(Duration::from_millis(100));
+                }
+                let n = num_ahead.fetch_add(1, Ordering::SeqCst) + 1;
+                max_ahead.fetch_max(n, Ordering::SeqCst);
+                *i
+            },
+            |r| {
+                num_ahead.fetch_sub(1, Ordering::SeqCst);
+                results.push(r);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(results, items);
+        assert!(max_ahead.load(Ordering::SeqCst) <= 8);
+    }
+
+    #[test]
+    fn walks_all_items() {
+        // every number below 1000 spawns its two children of a binary tree
+        let children = |i: u64| {
+            [2 * i + 1, 2 * i + 2]
+                .into_iter()
+                .filter(|c| *c < 1000)
+                .collect::<Vec<u64>>()
+        };
+        for jobs in [1, 4] {
+            let mut results = walk_parallel(vec![0], jobs, |i| (i, children(i)));
+            results.sort();
+            assert_eq!(results, (0..1000).collect::<Vec<u64>>());
+        }
+    }
+}